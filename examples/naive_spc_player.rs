@@ -1,20 +1,12 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use fixed_resample::ReadStatus;
-use spc700::spc::*;
+use spc700::renderer::*;
+use spc700::resampler::*;
 use spc700::spc_file::*;
-use spc700::types::*;
 use std::env;
 use std::fmt::Error;
-use std::num::NonZero;
-
-const CLOCK_TICK_CYCLE_64KHZ: u32 = 16; /* 64KHz周期のクロックサイクル SPCのクロック(1.024MHz)を64KHzで割って得られる = 1024000 / 64000 */
 
 /// 再生してみる
-fn naive_play(
-    register: &SPCRegister,
-    ram: &[u8],
-    dsp_register: &[u8; 128],
-) -> Result<(), Box<dyn std::error::Error>> {
+fn naive_play(spc_file: &SPCFile) -> Result<(), Box<dyn std::error::Error>> {
     const NUM_CHANNELS: usize = 2;
     const NORMALIZED_CONST: f32 = 1.0 / 32768.0;
 
@@ -27,55 +19,35 @@ fn naive_play(
     let sampling_rate = stream_config.sample_rate.0;
 
     // リサンプラ初期化 32k -> デバイスの出力レート変換となるように
-    let (mut prod, mut cons) = fixed_resample::resampling_channel::<f32, NUM_CHANNELS>(
-        NonZero::new(NUM_CHANNELS).unwrap(),
-        32000,
-        sampling_rate,
-        Default::default(),
-    );
+    let mut resampler = Resampler::new(sampling_rate, ResamplerQuality::High);
 
-    // SPCエミュレータ初期化
-    let mut emu: spc700::spc::SPC<spc700::sdsp::SDSP> = SPC::new(&register, ram, dsp_register);
-    let mut cycle_count = 0;
+    // レンダラ初期化
+    let mut renderer = Renderer::new(spc_file);
 
     // 再生ストリーム作成
-    let mut tmp_buffer = vec![0.0; 2048 * NUM_CHANNELS];
+    let mut tmp_buffer = vec![[0i16; 2]; 2048];
     let stream = device
         .build_output_stream(
             &stream_config,
             move |buffer: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                // レート変換比を信じ、バッファが一定量埋まるまで出力させる
-                let mut nsamples = prod.available_frames();
-                while nsamples > 1024 {
-                    let cycle = emu.execute_step();
-                    cycle_count += cycle as u32;
-                    if cycle_count >= CLOCK_TICK_CYCLE_64KHZ {
-                        cycle_count -= CLOCK_TICK_CYCLE_64KHZ;
-                        if let Some(out) = emu.clock_tick_64k_hz() {
-                            let fout = [
-                                (out[0] as f32) * NORMALIZED_CONST,
-                                (out[1] as f32) * NORMALIZED_CONST,
-                            ];
-                            prod.push_interleaved(&fout);
-                            nsamples = prod.available_frames();
-                        }
-                    }
-                }
-
-                // リサンプラー出力の取り出し
+                // 必要な分だけ演奏を進めてレート変換
                 let frames = buffer.len() / NUM_CHANNELS;
-                let status = cons.read_interleaved(&mut tmp_buffer[..frames * NUM_CHANNELS]);
-                if let ReadStatus::UnderflowOccurred { .. } = status {
-                    eprintln!("input stream fell behind: try increasing channel latency");
+                if tmp_buffer.len() < frames {
+                    tmp_buffer.resize(frames, [0; 2]);
                 }
+                resampler.process(&mut tmp_buffer[..frames], || {
+                    let mut out = [[0i16; 2]; 1];
+                    renderer.render(&mut out);
+                    out[0]
+                });
 
                 buffer.fill(0.0);
-                for ch in 0..NUM_CHANNELS {
-                    for (out_chunk, in_chunk) in buffer
-                        .chunks_exact_mut(NUM_CHANNELS)
-                        .zip(tmp_buffer.chunks_exact(NUM_CHANNELS))
-                    {
-                        out_chunk[ch] = in_chunk[ch];
+                for (out_chunk, in_frame) in buffer
+                    .chunks_exact_mut(NUM_CHANNELS)
+                    .zip(tmp_buffer[..frames].iter())
+                {
+                    for ch in 0..NUM_CHANNELS {
+                        out_chunk[ch] = (in_frame[ch] as f32) * NORMALIZED_CONST;
                     }
                 }
             },
@@ -112,39 +84,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             Music Duration: {} (sec) \n\
             Fadeout Time: {} (msec) \n\
             Composer: {}",
-            std::str::from_utf8(&spcfile.header.info).unwrap(),
+            spcfile.header.info_text(),
             spcfile.header.spc_register.pc,
             spcfile.header.spc_register.a,
             spcfile.header.spc_register.x,
             spcfile.header.spc_register.y,
             spcfile.header.spc_register.psw,
             spcfile.header.spc_register.sp,
-            std::str::from_utf8(&spcfile.header.music_title)
-                .unwrap()
-                .trim_end_matches('\0'),
-            std::str::from_utf8(&spcfile.header.game_title)
-                .unwrap()
-                .trim_end_matches('\0'),
-            std::str::from_utf8(&spcfile.header.creator)
-                .unwrap()
-                .trim_end_matches('\0'),
-            std::str::from_utf8(&spcfile.header.comment)
-                .unwrap()
-                .trim_end_matches('\0'),
+            spcfile.header.music_title_text(),
+            spcfile.header.game_title_text(),
+            spcfile.header.creator_text(),
+            spcfile.header.comment_text(),
             spcfile.header.generate_year,
             spcfile.header.generate_month,
             spcfile.header.generate_date,
             spcfile.header.duration,
             spcfile.header.fadeout_time,
-            std::str::from_utf8(&spcfile.header.composer)
-                .unwrap()
-                .trim_end_matches('\0'),
-        );
-        let _ = naive_play(
-            &spcfile.header.spc_register,
-            &spcfile.ram,
-            &spcfile.dsp_register,
+            spcfile.header.composer_text(),
         );
+        let _ = naive_play(&spcfile);
     }
 
     Ok(())
@@ -0,0 +1,95 @@
+use spc700::renderer::*;
+use spc700::spc_file::*;
+use spc700::wav::*;
+use std::env;
+use std::fmt::Error;
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+
+/// ファイルへの書き出し
+struct WavFile(File);
+
+impl WavOutput for WavFile {
+    type Error = std::io::Error;
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.0.write_all(data)
+    }
+
+    fn write_at(&mut self, position: u64, data: &[u8]) -> Result<(), Self::Error> {
+        let current = self.0.stream_position()?;
+        self.0.seek(SeekFrom::Start(position))?;
+        self.0.write_all(data)?;
+        self.0.seek(SeekFrom::Start(current))?;
+        Ok(())
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().collect();
+
+    // 引数が合っていないときは説明を表示
+    if args.len() < 3 || args.len() > 5 {
+        println!("Usage: {} SPC_FILE WAV_FILE [16|24|float] [stems]", args[0]);
+        return Err(Box::new(Error));
+    }
+    let stems = args.get(4).map(|s| s.as_str()) == Some("stems");
+
+    let format = match args.get(3).map(|s| s.as_str()) {
+        None | Some("16") => WavSampleFormat::PCM16,
+        Some("24") => WavSampleFormat::PCM24,
+        Some("float") => WavSampleFormat::Float32,
+        Some(_) => {
+            println!("Unsupported format: {}", args[3]);
+            return Err(Box::new(Error));
+        }
+    };
+
+    // データ読み込み
+    let data = std::fs::read(&args[1])?;
+    let spc_file = parse_spc_file(&data).ok_or(Error)?;
+
+    // 演奏時間分書き出し
+    let mut renderer = Renderer::new(&spc_file);
+    if renderer.total_samples().is_none() {
+        println!("Music duration is not specified.");
+        return Err(Box::new(Error));
+    }
+    if stems {
+        // ボイスごとに別ファイルへ書き出し（WAV_FILEの拡張子の前に名前を付与）
+        let base = args[2].strip_suffix(".wav").unwrap_or(&args[2]);
+        let names = [
+            "voice0", "voice1", "voice2", "voice3", "voice4", "voice5", "voice6", "voice7", "echo",
+        ];
+        let mut writers = Vec::with_capacity(NUM_STEMS);
+        for name in names.iter() {
+            let output = WavFile(File::create(format!("{}_{}.wav", base, name))?);
+            writers.push(WavWriter::new(output, format, Some(&spc_file.header))?);
+        }
+        let mut writers: [WavWriter<WavFile>; NUM_STEMS] = writers.try_into().map_err(|_| Error)?;
+        write_stems(&mut renderer, &mut writers)?;
+        for writer in writers {
+            writer.finalize()?;
+        }
+    } else {
+        let output = WavFile(File::create(&args[2])?);
+        let mut writer = WavWriter::new(output, format, Some(&spc_file.header))?;
+        let mut buffer = [[0i16; 2]; 4096];
+        loop {
+            let num_samples = renderer.render(&mut buffer);
+            if num_samples == 0 {
+                break;
+            }
+            writer.write_frames(&buffer[..num_samples])?;
+        }
+        writer.finalize()?;
+    }
+
+    // エミュレーションエラーで途中停止した場合は報告
+    if let Some(err) = renderer.error() {
+        println!("Rendering stopped by emulation error: {:?}", err);
+        return Err(Box::new(Error));
+    }
+
+    Ok(())
+}
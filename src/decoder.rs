@@ -1,3 +1,4 @@
+use crate::snapshot::*;
 use crate::types::*;
 
 /// BRRブロックサイズ
@@ -47,11 +48,34 @@ const GAUSS_INTERPOLATION_TABLE: [i32; 512] = [
     0x518, 0x518, 0x518, 0x519, 0x519,
 ];
 
+/// 前ブロックから引き継ぐサンプル数（補間のため）
+const DECODE_HISTORY_LENGTH: usize = 8;
+/// デコードバッファ長
+const DECODE_BUFFER_LENGTH: usize = DECODE_HISTORY_LENGTH + 16;
+/// 窓関数付きsinc補間の片側の長さ
+const SINC_INTERPOLATION_HALF_TAPS: i32 = 3;
+
+/// 補間方式
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum InterpolationMode {
+    /// ガウス補間（実機と同一）
+    #[default]
+    Gaussian,
+    /// 補間なし（直前のサンプルを保持）
+    None,
+    /// 線形補間
+    Linear,
+    /// 3次エルミート補間
+    Cubic,
+    /// 窓関数付きsinc補間（6タップ Lanczos窓）
+    Sinc,
+}
+
 /// デコーダ
 #[derive(Copy, Clone, Debug)]
 pub struct Decoder {
-    /// デコードバッファ（1ブロックの16サンプル+補間のための4サンプル）
-    decode_buffer: [i16; 20],
+    /// デコードバッファ（前ブロックの末尾8サンプル+1ブロックの16サンプル）
+    decode_buffer: [i16; DECODE_BUFFER_LENGTH],
     /// 直前のデコード値
     decode_history: [i16; 2],
     /// サンプル参照位置（固定小数。4bit整数+12bit小数）
@@ -66,6 +90,8 @@ pub struct Decoder {
     pub loop_flag: bool,
     /// 現在のブロックのENDフラグ
     pub end: bool,
+    /// ENDフラグのブロックを再生し終え、ループアドレスのブロックに移ったか（参照側でクリアする）
+    pub looped: bool,
 }
 
 /// 1サンプルをテーブルを使用して補間
@@ -81,6 +107,65 @@ fn interpolate_sample(decode_buffer: &[i16], interp_index: usize) -> i16 {
     output as i16
 }
 
+/// 正規化sinc関数
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1.0e-6 {
+        1.0
+    } else {
+        let px = core::f32::consts::PI * x;
+        libm::sinf(px) / px
+    }
+}
+
+/// 指定した方式で1サンプル補間
+/// decode_bufferは補間位置の前3サンプルと後3サンプル、interp_indexは8bitの小数位置
+fn interpolate_sample_with_mode(
+    decode_buffer: &[i16],
+    interp_index: usize,
+    mode: InterpolationMode,
+) -> i16 {
+    // ガウス補間と同じ4サンプル（補間位置はwindow[1]とwindow[2]の間）
+    let window = &decode_buffer[1..5];
+    let output = match mode {
+        InterpolationMode::Gaussian => return interpolate_sample(window, interp_index),
+        InterpolationMode::None => return window[1],
+        InterpolationMode::Linear => {
+            let s1 = window[1] as i32;
+            let s2 = window[2] as i32;
+            (s1 + (((s2 - s1) * interp_index as i32) >> 8)) as f32
+        }
+        InterpolationMode::Cubic => {
+            let t = interp_index as f32 / 256.0;
+            let [s0, s1, s2, s3] = [
+                window[0] as f32,
+                window[1] as f32,
+                window[2] as f32,
+                window[3] as f32,
+            ];
+            // Catmull-Romスプライン
+            let a = -0.5 * s0 + 1.5 * s1 - 1.5 * s2 + 0.5 * s3;
+            let b = s0 - 2.5 * s1 + 2.0 * s2 - 0.5 * s3;
+            let c = -0.5 * s0 + 0.5 * s2;
+            ((a * t + b) * t + c) * t + s1
+        }
+        InterpolationMode::Sinc => {
+            let t = interp_index as f32 / 256.0;
+            let mut sum = 0.0;
+            let mut weight_sum = 0.0;
+            for (k, &sample) in decode_buffer.iter().enumerate() {
+                // 補間位置からの距離
+                let x = (k as i32 - SINC_INTERPOLATION_HALF_TAPS + 1) as f32 - t;
+                let weight = sinc(x) * sinc(x / SINC_INTERPOLATION_HALF_TAPS as f32);
+                sum += weight * sample as f32;
+                weight_sum += weight;
+            }
+            sum / weight_sum
+        }
+    };
+
+    libm::roundf(output).clamp(-32768.0, 32767.0) as i16
+}
+
 /// 1サンプルデコード
 fn decode_brr_sample(history: &mut [i16], filter: u8, granularity: u8, nibble: u8) -> i16 {
     assert!(nibble <= 0xF);
@@ -103,7 +188,7 @@ fn decode_brr_sample(history: &mut [i16], filter: u8, granularity: u8, nibble: u
     let mut output = (sample << (scale as i32)) >> 1;
     let p1 = history[1] as i32;
     let p2 = history[0] as i32;
-    match filter {
+    match filter & 0x3 {
         0 => {}
         1 => {
             // output + (15 / 16) * p1
@@ -117,14 +202,14 @@ fn decode_brr_sample(history: &mut [i16], filter: u8, granularity: u8, nibble: u
             output -= p2;
             output += p2 >> 4;
         }
-        3 => {
+        _ => {
+            // フィルタは2bit値のため残りは3のみ
             // output + (115 / 64) * p1 - (13 / 16) * p2
             output += p1 << 1;
             output += (-13 * p1) >> 6;
             output -= p2;
             output += (3 * p2) >> 4;
         }
-        _ => panic!("Invalid BRR filter!"),
     }
 
     // 16bit幅に制限（重要）
@@ -168,7 +253,7 @@ fn decode_brr_block_signal(
 impl Decoder {
     pub fn new() -> Self {
         Self {
-            decode_buffer: [0; 20],
+            decode_buffer: [0; DECODE_BUFFER_LENGTH],
             decode_history: [0; 2],
             decode_start_address: 0,
             decode_loop_address: 0,
@@ -176,6 +261,7 @@ impl Decoder {
             sample_index_fixed: 0,
             loop_flag: false,
             end: false,
+            looped: false,
         }
     }
 
@@ -187,13 +273,16 @@ impl Decoder {
     }
 
     /// キーオン時の処理
+    /// デコード履歴をリセットし、開始アドレスの最初のブロックをデコードする
     pub fn keyon(&mut self, ram: &[u8], dir_address: usize) {
         self.end = false;
+        self.looped = false;
         self.set_address(ram, dir_address);
         self.decode_read_pos = self.decode_start_address;
         self.sample_index_fixed = 0;
         self.decode_buffer.fill(0);
         self.decode_history.fill(0);
+        self.decode_next_block(ram);
     }
 
     /// 1ブロックデコード
@@ -205,10 +294,8 @@ impl Decoder {
         // ブロックヘッダデコード
         (granularity, filter, self.loop_flag, self.end) = decode_brr_block_header(ram[0]);
 
-        // 末尾4サンプルを先頭に移動（補間のため）
-        for i in 0..4 {
-            self.decode_buffer[i] = self.decode_buffer[16 + i];
-        }
+        // 末尾のサンプルを先頭に移動（補間のため）
+        self.decode_buffer.copy_within(16.., 0);
 
         // 1ブロックデコード
         decode_brr_block_signal(
@@ -216,12 +303,36 @@ impl Decoder {
             granularity,
             filter,
             &ram[1..],
-            &mut self.decode_buffer[4..],
+            &mut self.decode_buffer[DECODE_HISTORY_LENGTH..],
         );
     }
 
+    /// 読み込み位置のブロックをデコードし、次のブロックに進める
+    fn decode_next_block(&mut self, ram: &[u8]) {
+        // 1ブロックデコード
+        if (self.decode_read_pos + BRR_BLOCK_SIZE) <= 0xFFFF {
+            self.decode_brr_block(&ram[self.decode_read_pos..]);
+        } else {
+            // デコードアドレスが16bitを超える場合は、RAMの先頭に戻る
+            let mut decode_ram = [0u8; BRR_BLOCK_SIZE];
+            let remain = 0x10000 - self.decode_read_pos;
+            decode_ram[..remain].copy_from_slice(&ram[self.decode_read_pos..]);
+            decode_ram[remain..].copy_from_slice(&ram[..(BRR_BLOCK_SIZE - remain)]);
+            self.decode_brr_block(&decode_ram);
+        }
+        if self.end {
+            // 末尾に達していたらループ開始アドレスに戻る
+            self.decode_read_pos = self.decode_loop_address;
+        } else {
+            // 次のブロックに進む
+            self.decode_read_pos += BRR_BLOCK_SIZE;
+            // アドレスの回り込みを考慮
+            self.decode_read_pos &= 0xFFFF;
+        }
+    }
+
     /// 1サンプル出力
-    pub fn process(&mut self, ram: &[u8], pitch: u16) -> i16 {
+    pub fn process(&mut self, ram: &[u8], pitch: u16, mode: InterpolationMode) -> i16 {
         let next_block;
 
         // サンプルを進める
@@ -229,33 +340,59 @@ impl Decoder {
 
         // バッファが尽きたら次のブロックをデコード
         if next_block {
-            // 1ブロックデコード
-            if (self.decode_read_pos + BRR_BLOCK_SIZE) <= 0xFFFF {
-                self.decode_brr_block(&ram[self.decode_read_pos..]);
-            } else {
-                // デコードアドレスが16bitを超える場合は、RAMの先頭に戻る
-                let mut decode_ram = [0u8; BRR_BLOCK_SIZE];
-                let remain = 0x10000 - self.decode_read_pos;
-                decode_ram[..remain].copy_from_slice(&ram[self.decode_read_pos..]);
-                decode_ram[remain..].copy_from_slice(&ram[..(BRR_BLOCK_SIZE - remain)]);
-                self.decode_brr_block(&decode_ram);
-            }
+            // ENDフラグのブロックを再生し終えた
             if self.end {
-                // 末尾に達していたらループ開始アドレスに戻る
-                self.decode_read_pos = self.decode_loop_address;
-            } else {
-                // 次のブロックに進む
-                self.decode_read_pos += BRR_BLOCK_SIZE;
-                // アドレスの回り込みを考慮
-                self.decode_read_pos &= 0xFFFF;
+                self.looped = true;
             }
+            self.decode_next_block(ram);
         }
 
         // 補間して出力
-        let index = (self.sample_index_fixed >> 12) as usize;
-        interpolate_sample(
-            &self.decode_buffer[index..(index + 4)],
+        let index = (self.sample_index_fixed >> 12) as usize + DECODE_HISTORY_LENGTH - 5;
+        interpolate_sample_with_mode(
+            &self.decode_buffer[index..(index + 6)],
             ((self.sample_index_fixed >> 4) & 0xFF) as usize,
+            mode,
         )
     }
 }
+
+impl Snapshot for Decoder {
+    fn save_state(&self, writer: &mut SnapshotWriter) -> Result<(), SnapshotError> {
+        for sample in self.decode_buffer.iter() {
+            writer.write_i16(*sample)?;
+        }
+        for sample in self.decode_history.iter() {
+            writer.write_i16(*sample)?;
+        }
+        writer.write_u16(self.sample_index_fixed)?;
+        writer.write_usize(self.decode_start_address)?;
+        writer.write_usize(self.decode_loop_address)?;
+        writer.write_usize(self.decode_read_pos)?;
+        writer.write_bool(self.loop_flag)?;
+        writer.write_bool(self.end)
+    }
+
+    fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        for sample in self.decode_buffer.iter_mut() {
+            *sample = reader.read_i16()?;
+        }
+        for sample in self.decode_history.iter_mut() {
+            *sample = reader.read_i16()?;
+        }
+        self.sample_index_fixed = reader.read_u16()?;
+        self.decode_start_address = reader.read_usize()?;
+        self.decode_loop_address = reader.read_usize()?;
+        self.decode_read_pos = reader.read_usize()?;
+        self.loop_flag = reader.read_bool()?;
+        self.end = reader.read_bool()?;
+        // アドレスは16bit空間内でなければならない
+        if self.decode_start_address > 0xFFFF
+            || self.decode_loop_address > 0xFFFF
+            || self.decode_read_pos > 0xFFFF
+        {
+            return Err(SnapshotError::InvalidData);
+        }
+        Ok(())
+    }
+}
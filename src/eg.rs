@@ -1,3 +1,5 @@
+use crate::snapshot::*;
+
 /// ボイスゲインとそのパラメータ
 #[derive(Copy, Clone, Debug)]
 enum GainMode {
@@ -47,6 +49,8 @@ pub struct EnvelopeGenerator {
     sustain_rate: u8,
     /// サステイン状態に移行するゲイン値
     sustain_level: u8,
+    /// ADSR2設定値
+    adsr2_value: u8,
     /// 最後に計算したゲイン適用値
     pub gain: i32,
 }
@@ -65,6 +69,11 @@ const COUNTER_OFFSETS: [u16; 32] = [
     536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 0, 0,
 ];
 
+/// グローバルカウンタがレートに対応するイベントを発生させるか判定
+pub fn check_counter_event(global_counter: u16, rate: u8) -> bool {
+    (rate > 0) && ((global_counter + COUNTER_OFFSETS[rate as usize]) % COUNTER_RATES[rate as usize] == 0)
+}
+
 impl EnvelopeGenerator {
     pub fn new() -> Self {
         Self {
@@ -73,10 +82,11 @@ impl EnvelopeGenerator {
             gain_mode: GainMode::Fixed { gain: 0 },
             gain_value: 0,
             state: EnvelopeState::Release,
-            attack_rate: 0,
-            decay_rate: 0,
+            attack_rate: 1,
+            decay_rate: 16,
             sustain_rate: 0,
             sustain_level: 0,
+            adsr2_value: 0,
             gain: 0,
         }
     }
@@ -147,6 +157,8 @@ impl EnvelopeGenerator {
 
     /// ADSR2の設定処理
     pub fn set_adsr2(&mut self, value: u8) {
+        // 読み出しのため設定値を保持
+        self.adsr2_value = value;
         self.sustain_rate = value & 0x1F;
         if self.adsr_enable {
             self.sustain_level = (value >> 5) & 0x7;
@@ -199,12 +211,15 @@ impl EnvelopeGenerator {
     /// ADSR1の取得処理
     pub fn get_adsr1(&self) -> u8 {
         let adsr_flag = if self.adsr_enable { 0x80 } else { 0x00 };
-        adsr_flag | (self.decay_rate << 4) | self.attack_rate
+        // レートから設定値に戻す
+        let attack = (self.attack_rate - 1) / 2;
+        let decay = (self.decay_rate - 16) / 2;
+        adsr_flag | (decay << 4) | attack
     }
 
     /// ADSR2の取得処理
     pub fn get_adsr2(&self) -> u8 {
-        (self.sustain_level << 5) | self.sustain_rate
+        self.adsr2_value
     }
 
     /// GAINの取得処理
@@ -215,7 +230,7 @@ impl EnvelopeGenerator {
     /// エンベロープ状態更新
     pub fn update(&mut self, global_counter: u16) -> bool {
         // アクション発生判定
-        let updated = (self.rate > 0) && ((global_counter + COUNTER_OFFSETS[self.rate as usize]) % COUNTER_RATES[self.rate as usize] == 0);
+        let updated = check_counter_event(global_counter, self.rate);
         if updated {
             // エンベロープゲイン更新
             if self.state == EnvelopeState::Release {
@@ -290,3 +305,66 @@ impl EnvelopeGenerator {
         updated
     }
 }
+
+impl Snapshot for EnvelopeGenerator {
+    fn save_state(&self, writer: &mut SnapshotWriter) -> Result<(), SnapshotError> {
+        writer.write_u8(self.rate)?;
+        writer.write_bool(self.adsr_enable)?;
+        let (mode, param) = match self.gain_mode {
+            GainMode::Fixed { gain } => (0, gain),
+            GainMode::LinearDecrease { rate } => (1, rate),
+            GainMode::ExponentialDecrease { rate } => (2, rate),
+            GainMode::LinearIncrease { rate } => (3, rate),
+            GainMode::BentIncrease { rate } => (4, rate),
+        };
+        writer.write_u8(mode)?;
+        writer.write_u8(param)?;
+        writer.write_u8(self.gain_value)?;
+        writer.write_u8(match self.state {
+            EnvelopeState::Attack => 0,
+            EnvelopeState::Decay => 1,
+            EnvelopeState::Sustain => 2,
+            EnvelopeState::Release => 3,
+        })?;
+        writer.write_u8(self.attack_rate)?;
+        writer.write_u8(self.decay_rate)?;
+        writer.write_u8(self.sustain_rate)?;
+        writer.write_u8(self.sustain_level)?;
+        writer.write_u8(self.adsr2_value)?;
+        writer.write_i32(self.gain)
+    }
+
+    fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.rate = reader.read_u8()?;
+        self.adsr_enable = reader.read_bool()?;
+        let mode = reader.read_u8()?;
+        let param = reader.read_u8()?;
+        self.gain_mode = match mode {
+            0 => GainMode::Fixed { gain: param },
+            1 => GainMode::LinearDecrease { rate: param },
+            2 => GainMode::ExponentialDecrease { rate: param },
+            3 => GainMode::LinearIncrease { rate: param },
+            4 => GainMode::BentIncrease { rate: param },
+            _ => return Err(SnapshotError::InvalidData),
+        };
+        self.gain_value = reader.read_u8()?;
+        self.state = match reader.read_u8()? {
+            0 => EnvelopeState::Attack,
+            1 => EnvelopeState::Decay,
+            2 => EnvelopeState::Sustain,
+            3 => EnvelopeState::Release,
+            _ => return Err(SnapshotError::InvalidData),
+        };
+        self.attack_rate = reader.read_u8()?;
+        self.decay_rate = reader.read_u8()?;
+        self.sustain_rate = reader.read_u8()?;
+        self.sustain_level = reader.read_u8()?;
+        self.adsr2_value = reader.read_u8()?;
+        self.gain = reader.read_i32()?;
+        // レートはテーブル範囲内でなければならない
+        if self.rate > 31 || self.attack_rate == 0 || self.decay_rate < 16 {
+            return Err(SnapshotError::InvalidData);
+        }
+        Ok(())
+    }
+}
@@ -2,9 +2,15 @@
 #![cfg_attr(not(test), no_std)]
 pub mod types;
 pub mod spc_file;
+pub mod spc2_file;
 pub mod assembler;
 pub mod spc;
 pub mod sdsp;
 pub mod mididsp;
 pub mod decoder;
+pub mod snapshot;
+pub mod renderer;
+pub mod resampler;
+pub mod wav;
+pub mod text;
 mod eg;
@@ -1,4 +1,5 @@
 use crate::eg::*;
+use crate::snapshot::*;
 use crate::types::*;
 use core::f32::consts::PI;
 use libm;
@@ -168,6 +169,10 @@ pub struct MIDIDSP {
     playback_parameter_update_period: u16,
     /// 最後に出力したチャンネルメッセージのステータスバイト
     status_byte: u8,
+    /// 書き込まれたレジスタ値（未使用アドレスの読み出し用）
+    register: [u8; 128],
+    /// 厳格モードか
+    strict_mode: bool,
 }
 
 /// ステータスバイト情報付きMIDIメッセージ
@@ -511,6 +516,164 @@ impl MIDIVoiceRegister {
     }
 }
 
+/// スナップショット内でMIDIDSPの状態を示す識別子
+const MIDIDSP_SNAPSHOT_TAG: [u8; 4] = *b"MDSP";
+
+impl Snapshot for MIDIVoiceRegister {
+    fn save_state(&self, writer: &mut SnapshotWriter) -> Result<(), SnapshotError> {
+        writer.write_u8(self.channel)?;
+        writer.write_i8(self.volume[0])?;
+        writer.write_i8(self.volume[1])?;
+        writer.write_u16(self.pitch)?;
+        writer.write_usize(self.brr_dir_address_base)?;
+        writer.write_u8(self.sample_source)?;
+        self.eg.save_state(writer)?;
+        writer.write_bool(self.keyon)?;
+        writer.write_bool(self.keyoff)?;
+        writer.write_bool(self.noteon)?;
+        writer.write_bool(self.noteon_drum)?;
+        writer.write_bool(self.pitch_mod)?;
+        writer.write_bool(self.noise)?;
+        writer.write_bool(self.echo)?;
+        writer.write_bool(self.envelope_updated)?;
+        writer.write_u8(self.last_volume)?;
+        writer.write_u8(self.last_pan)?;
+        writer.write_u8(self.last_note)?;
+        writer.write_u16(self.pitch_bend_base)?;
+        writer.write_u16(self.last_pitch)?;
+        writer.write_u8(self.last_program)?;
+        writer.write_bool(self.ch_mute)
+    }
+
+    fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.channel = reader.read_u8()?;
+        self.volume[0] = reader.read_i8()?;
+        self.volume[1] = reader.read_i8()?;
+        self.pitch = reader.read_u16()?;
+        self.brr_dir_address_base = reader.read_usize()?;
+        self.sample_source = reader.read_u8()?;
+        self.eg.load_state(reader)?;
+        self.keyon = reader.read_bool()?;
+        self.keyoff = reader.read_bool()?;
+        self.noteon = reader.read_bool()?;
+        self.noteon_drum = reader.read_bool()?;
+        self.pitch_mod = reader.read_bool()?;
+        self.noise = reader.read_bool()?;
+        self.echo = reader.read_bool()?;
+        self.envelope_updated = reader.read_bool()?;
+        self.last_volume = reader.read_u8()?;
+        self.last_pan = reader.read_u8()?;
+        self.last_note = reader.read_u8()?;
+        self.pitch_bend_base = reader.read_u16()?;
+        self.last_pitch = reader.read_u16()?;
+        self.last_program = reader.read_u8()?;
+        self.ch_mute = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for SampleSourceMap {
+    fn save_state(&self, writer: &mut SnapshotWriter) -> Result<(), SnapshotError> {
+        for i in 0..256 {
+            writer.write_bool(self.mute[i])?;
+            writer.write_u8(self.program[i])?;
+            writer.write_u16(self.center_note[i])?;
+            writer.write_u8(self.noteon_velocity[i])?;
+            writer.write_u8(self.pitch_bend_sensitibity[i])?;
+            writer.write_bool(self.output_envelope[i])?;
+            writer.write_bool(self.auto_pan[i])?;
+            writer.write_u8(self.fixed_pan[i])?;
+            writer.write_bool(self.auto_volume[i])?;
+            writer.write_u8(self.fixed_volume[i])?;
+            writer.write_bool(self.output_pitch_bend[i])?;
+            writer.write_bool(self.echo_as_effect1_depth[i])?;
+            writer.write_bool(self.pitch_bend_sensitibity_updated[i])?;
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        for i in 0..256 {
+            self.mute[i] = reader.read_bool()?;
+            self.program[i] = reader.read_u8()?;
+            self.center_note[i] = reader.read_u16()?;
+            self.noteon_velocity[i] = reader.read_u8()?;
+            self.pitch_bend_sensitibity[i] = reader.read_u8()?;
+            self.output_envelope[i] = reader.read_bool()?;
+            self.auto_pan[i] = reader.read_bool()?;
+            self.fixed_pan[i] = reader.read_u8()?;
+            self.auto_volume[i] = reader.read_bool()?;
+            self.fixed_volume[i] = reader.read_u8()?;
+            self.output_pitch_bend[i] = reader.read_bool()?;
+            self.echo_as_effect1_depth[i] = reader.read_bool()?;
+            self.pitch_bend_sensitibity_updated[i] = reader.read_bool()?;
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot for MIDIDSP {
+    fn save_state(&self, writer: &mut SnapshotWriter) -> Result<(), SnapshotError> {
+        writer.write_bytes(&MIDIDSP_SNAPSHOT_TAG)?;
+        writer.write_i8(self.volume[0])?;
+        writer.write_i8(self.volume[1])?;
+        writer.write_i8(self.echo_volume[0])?;
+        writer.write_i8(self.echo_volume[1])?;
+        writer.write_u8(self.flag)?;
+        writer.write_bool(self.mute)?;
+        writer.write_u8(self.noise_clock)?;
+        writer.write_u8(self.brr_dir_page)?;
+        writer.write_u16(self.global_counter)?;
+        for voice in self.voice.iter() {
+            voice.save_state(writer)?;
+        }
+        self.sample_source_map.save_state(writer)?;
+        writer.write_usize(self.sample_source_target)?;
+        writer.write_u16(self.playback_parameter_count)?;
+        writer.write_u16(self.playback_parameter_update_period)?;
+        writer.write_u8(self.status_byte)?;
+        writer.write_bytes(&self.register)
+    }
+
+    fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.expect_tag(&MIDIDSP_SNAPSHOT_TAG)?;
+        self.volume[0] = reader.read_i8()?;
+        self.volume[1] = reader.read_i8()?;
+        self.echo_volume[0] = reader.read_i8()?;
+        self.echo_volume[1] = reader.read_i8()?;
+        self.flag = reader.read_u8()?;
+        self.mute = reader.read_bool()?;
+        self.noise_clock = reader.read_u8()?;
+        self.brr_dir_page = reader.read_u8()?;
+        self.global_counter = reader.read_u16()?;
+        for voice in self.voice.iter_mut() {
+            voice.load_state(reader)?;
+        }
+        self.sample_source_map.load_state(reader)?;
+        self.sample_source_target = reader.read_usize()?;
+        self.playback_parameter_count = reader.read_u16()?;
+        self.playback_parameter_update_period = reader.read_u16()?;
+        self.status_byte = reader.read_u8()?;
+        reader.read_bytes(&mut self.register)?;
+        // 範囲外の値はエミュレーション中にパニックを起こすため拒否
+        if self.sample_source_target > 0xFF || self.global_counter >= 0x7800 {
+            return Err(SnapshotError::InvalidData);
+        }
+        Ok(())
+    }
+}
+
+impl MIDIDSP {
+    /// 未使用アドレスのレジスタ読み込み
+    fn read_unused_register(&self, address: u8) -> Result<u8, SPCError> {
+        if self.strict_mode {
+            return Err(SPCError::UnsupportedDSPAddress { address });
+        }
+        // 実機では書き込んだ値がそのまま読み出せる
+        Ok(self.register[(address & 0x7F) as usize])
+    }
+}
+
 impl SPCDSP for MIDIDSP {
     type Output = MIDIOutput;
 
@@ -553,23 +716,31 @@ impl SPCDSP for MIDIDSP {
             playback_parameter_count: 0,
             playback_parameter_update_period: 160,
             status_byte: 0,
+            register: [0; 128],
+            strict_mode: false,
         }
     }
 
     /// 128バイトメモリから初期化
     fn initialize(&mut self, ram: &mut [u8], dsp_register: &[u8; 128]) {
         // DIRは先に設定（初期状態でKONがある場合にアドレスを正しくするため）
-        self.write_register(ram, DSP_ADDRESS_DIR, dsp_register[DSP_ADDRESS_DIR as usize]);
+        let _ = self.write_register(ram, DSP_ADDRESS_DIR, dsp_register[DSP_ADDRESS_DIR as usize]);
 
         // すべてのレジスタを設定
         for i in 0..128 {
-            self.write_register(ram, i, dsp_register[i as usize]);
+            let _ = self.write_register(ram, i, dsp_register[i as usize]);
         }
     }
 
+    /// 厳格モードの設定
+    fn set_strict_mode(&mut self, strict: bool) {
+        self.strict_mode = strict;
+    }
+
     /// DSPレジスタの書き込み処理
-    fn write_register(&mut self, _ram: &[u8], address: u8, value: u8) {
+    fn write_register(&mut self, _ram: &[u8], address: u8, value: u8) -> Result<(), SPCError> {
         trace!("DSPW: {:02X} <- {:02X}", address, value);
+        self.register[(address & 0x7F) as usize] = value;
         match address & 0x7F {
             DSP_ADDRESS_MVOLL => {
                 self.volume[0] = value as i8;
@@ -745,13 +916,14 @@ impl SPCDSP for MIDIDSP {
                 // 他のアドレスへの書き込みは効果なし
             }
         }
+        Ok(())
     }
 
     /// DSPレジスタの読み込み処理
-    fn read_register(&self, _ram: &[u8], address: u8) -> u8 {
+    fn read_register(&self, _ram: &[u8], address: u8) -> Result<u8, SPCError> {
         trace!("DSPR: {:02X}", address);
         // 80-FFの読み込みは00-7Fと同等に扱われる
-        match address & 0x7F {
+        let value = match address & 0x7F {
             DSP_ADDRESS_MVOLL => self.volume[0] as u8,
             DSP_ADDRESS_MVOLR => self.volume[1] as u8,
             DSP_ADDRESS_EVOLL => self.echo_volume[0] as u8,
@@ -904,15 +1076,12 @@ impl SPCDSP for MIDIDSP {
                     DSP_ADDRESS_V0GAIN => self.voice[ch].eg.get_gain(),
                     DSP_ADDRESS_V0ENVX => ((self.voice[ch].eg.gain >> 4) & 0xFF) as u8,
                     DSP_ADDRESS_V0OUTX => 0, // 0を返す
-                    _ => {
-                        panic!("Unsupported DSP address!");
-                    }
+                    _ => self.read_unused_register(address)?,
                 }
             }
-            _ => {
-                panic!("Unsupported DSP address!");
-            }
-        }
+            _ => self.read_unused_register(address)?,
+        };
+        Ok(value)
     }
 
     /// 32kHz周期処理
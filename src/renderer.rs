@@ -0,0 +1,319 @@
+use crate::sdsp::*;
+use crate::spc::*;
+use crate::spc_file::*;
+use crate::types::SPCError;
+
+/// 出力サンプリングレート
+pub const OUTPUT_SAMPLING_RATE: u32 = 32000;
+/// 1サンプルあたりの64kHzティック数
+const TICKS_PER_SAMPLE: u64 = 2;
+/// 対数フェードで到達する減衰量（-60dB）
+const LOG_FADE_ATTENUATION: f32 = 1.0e-3;
+
+/// フェードアウトの形状
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FadeCurve {
+    /// 振幅を線形に減衰
+    Linear,
+    /// 振幅をデシベル上で線形に減衰
+    Logarithmic,
+}
+
+/// SPCファイルをPCMに書き出すレンダラ
+pub struct Renderer {
+    /// SPCエミュレータ
+    spc: SPC<SDSP>,
+    /// 64kHzティックに満たないサイクル数
+    clock_cycle: u32,
+    /// 出力したサンプル数
+    sample_count: u64,
+    /// フェードアウト開始までのサンプル数（Noneなら無制限）
+    play_length: Option<u64>,
+    /// フェードアウトのサンプル数
+    fade_length: u64,
+    /// フェードアウトの形状
+    fade_curve: FadeCurve,
+    /// 出力を停止させたエミュレータのエラー
+    error: Option<SPCError>,
+}
+
+/// 秒数からサンプル数に変換
+pub fn seconds_to_samples(seconds: u32) -> u64 {
+    seconds as u64 * OUTPUT_SAMPLING_RATE as u64
+}
+
+/// SPCファイルのタグから演奏時間とフェードアウト時間（サンプル数）を取得
+/// 拡張ID666に時間の指定があればそちらを優先する。演奏時間が不明な場合はNone
+pub fn play_length_from_tag(spc_file: &SPCFile) -> (Option<u64>, u64) {
+    let header = &spc_file.header;
+    let mut play_length = if header.duration != 0 {
+        Some(seconds_to_samples(header.duration as u32))
+    } else {
+        None
+    };
+    let mut fade_length = header.fadeout_time as u64 * (OUTPUT_SAMPLING_RATE as u64 / 1000);
+
+    if let Some(tag) = &spc_file.extended_tag {
+        // 拡張ID666の時間は1/64000秒単位
+        if let Some(intro_length) = tag.intro_length {
+            let loop_count = tag.loop_count.unwrap_or(1) as u64;
+            let ticks = intro_length as u64
+                + tag.loop_length.unwrap_or(0) as u64 * loop_count
+                + tag.end_length.unwrap_or(0) as u64;
+            play_length = Some(ticks / TICKS_PER_SAMPLE);
+        }
+        if let Some(fade) = tag.fade_length {
+            fade_length = fade as u64 / TICKS_PER_SAMPLE;
+        }
+    }
+
+    (play_length, fade_length)
+}
+
+/// ゲインの適用
+fn apply_gain(frame: [i16; 2], gain: f32) -> [i16; 2] {
+    if gain >= 1.0 {
+        frame
+    } else {
+        [
+            (frame[0] as f32 * gain) as i16,
+            (frame[1] as f32 * gain) as i16,
+        ]
+    }
+}
+
+impl Renderer {
+    /// SPCファイルからレンダラを作成
+    /// 演奏時間・フェードアウト時間はタグの値を使用する
+    pub fn new(spc_file: &SPCFile) -> Self {
+        let mut spc = SPC::new(
+            &spc_file.header.spc_register,
+            &spc_file.ram,
+            &spc_file.dsp_register,
+        );
+        // 既定値に関わらず、書き出しがエラーで止まらないよう厳格モードは常に無効にする
+        spc.set_strict_mode(false);
+        let (play_length, fade_length) = play_length_from_tag(spc_file);
+        Self {
+            spc,
+            clock_cycle: 0,
+            sample_count: 0,
+            play_length,
+            fade_length,
+            fade_curve: FadeCurve::Linear,
+            error: None,
+        }
+    }
+
+    /// 演奏時間とフェードアウト時間（サンプル数）を設定
+    /// 演奏時間がNoneの場合は終了せずフェードアウトも行わない
+    pub fn set_play_length(&mut self, play_length: Option<u64>, fade_length: u64) {
+        self.play_length = play_length;
+        self.fade_length = fade_length;
+    }
+
+    /// フェードアウトの形状を設定
+    pub fn set_fade_curve(&mut self, curve: FadeCurve) {
+        self.fade_curve = curve;
+    }
+
+    /// フェードアウトを含む全体のサンプル数（無制限ならNone）
+    pub fn total_samples(&self) -> Option<u64> {
+        self.play_length.map(|length| length + self.fade_length)
+    }
+
+    /// 出力したサンプル数
+    pub fn sample_count(&self) -> u64 {
+        self.sample_count
+    }
+
+    /// 全て出力し終えたか（エラーで停止した場合も終了とみなす）
+    pub fn is_finished(&self) -> bool {
+        if self.error.is_some() {
+            return true;
+        }
+        match self.total_samples() {
+            Some(total) => self.sample_count >= total,
+            None => false,
+        }
+    }
+
+    /// 出力を停止させたエラー（発生していなければNone）
+    pub fn error(&self) -> Option<SPCError> {
+        self.error
+    }
+
+    /// エミュレータの参照
+    pub fn spc(&self) -> &SPC<SDSP> {
+        &self.spc
+    }
+
+    /// エミュレータの可変参照
+    pub fn spc_mut(&mut self) -> &mut SPC<SDSP> {
+        &mut self.spc
+    }
+
+    /// 1サンプル分エミュレータを進め、出力を得る（フェードアウトは適用しない）
+    pub fn next_sample(&mut self) -> Result<[i16; 2], SPCError> {
+        loop {
+            // 前回の余りのサイクルから消化する
+            while self.clock_cycle >= CLOCK_TICK_CYCLE_64KHZ {
+                self.clock_cycle -= CLOCK_TICK_CYCLE_64KHZ;
+                if let Some(out) = self.spc.clock_tick_64k_hz() {
+                    return Ok(out);
+                }
+            }
+            let cycle = self.spc.execute_step()?;
+            self.clock_cycle += cycle as u32;
+        }
+    }
+
+    /// 次のサンプルを出力。エラーが発生した場合は記録してNoneを返す
+    fn next_sample_or_stop(&mut self) -> Option<[i16; 2]> {
+        match self.next_sample() {
+            Ok(sample) => Some(sample),
+            Err(err) => {
+                self.error = Some(err);
+                None
+            }
+        }
+    }
+
+    /// 現在位置のフェードアウトのゲイン
+    fn fade_gain(&self) -> f32 {
+        let play_length = match self.play_length {
+            Some(length) if self.sample_count >= length => length,
+            _ => return 1.0,
+        };
+        if self.fade_length == 0 {
+            return 0.0;
+        }
+        // フェードアウトの最後のサンプルで0になるようにする
+        let progress = (self.sample_count - play_length + 1) as f32 / self.fade_length as f32;
+        match self.fade_curve {
+            FadeCurve::Linear => 1.0 - progress,
+            FadeCurve::Logarithmic => {
+                // -60dBまで減衰させ、終端で0になるよう補正
+                let gain = libm::powf(LOG_FADE_ATTENUATION, progress);
+                (gain - LOG_FADE_ATTENUATION) / (1.0 - LOG_FADE_ATTENUATION)
+            }
+        }
+    }
+
+    /// バッファを埋めるまで出力。書き込んだサンプル数を返す
+    /// 演奏が終了した場合・エラーで停止した場合、残りのバッファは無音で埋める
+    pub fn render(&mut self, out: &mut [[i16; 2]]) -> usize {
+        let mut num_samples = 0;
+        for frame in out.iter_mut() {
+            if self.is_finished() {
+                break;
+            }
+            let Some(sample) = self.next_sample_or_stop() else {
+                break;
+            };
+            *frame = apply_gain(sample, self.fade_gain());
+            self.sample_count += 1;
+            num_samples += 1;
+        }
+        out[num_samples..].fill([0, 0]);
+        num_samples
+    }
+
+    /// ミックス出力とボイスごとの出力を同時に出力。書き込んだサンプル数を返す
+    /// 出力するサンプル数は短い方のバッファに合わせる。フェードアウトはボイスごとの出力にも適用する
+    pub fn render_with_stems(&mut self, out: &mut [[i16; 2]], stems: &mut [SDSPStems]) -> usize {
+        let length = out.len().min(stems.len());
+        let mut num_samples = 0;
+        for (frame, stem) in out[..length].iter_mut().zip(stems[..length].iter_mut()) {
+            if self.is_finished() {
+                break;
+            }
+            let Some(sample) = self.next_sample_or_stop() else {
+                break;
+            };
+            let gain = self.fade_gain();
+            *frame = apply_gain(sample, gain);
+            *stem = *self.spc.dsp.stems();
+            for voice in stem.voice.iter_mut() {
+                *voice = apply_gain(*voice, gain);
+            }
+            stem.echo = apply_gain(stem.echo, gain);
+            self.sample_count += 1;
+            num_samples += 1;
+        }
+        out[num_samples..length].fill([0, 0]);
+        stems[num_samples..length].fill(SDSPStems::default());
+        num_samples
+    }
+
+    /// 指定秒数（バッファに収まる分まで）出力。書き込んだサンプル数を返す
+    pub fn render_seconds(&mut self, seconds: u32, out: &mut [[i16; 2]]) -> usize {
+        let length = (seconds_to_samples(seconds) as usize).min(out.len());
+        self.render(&mut out[..length])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bench_spc_file() -> SPCFile {
+        let data = std::fs::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/benches/data/Eyes on Me! [2 = Original] [1 = OG Echo].spc"
+        ))
+        .unwrap();
+        parse_spc_file(&data).unwrap()
+    }
+
+    #[test]
+    fn play_length_from_id666_and_xid6() {
+        let mut spc_file = bench_spc_file();
+        spc_file.extended_tag = None;
+        spc_file.header.duration = 10;
+        spc_file.header.fadeout_time = 2000;
+        assert_eq!(play_length_from_tag(&spc_file), (Some(320000), 64000));
+
+        // 演奏時間が0なら無制限
+        spc_file.header.duration = 0;
+        assert_eq!(play_length_from_tag(&spc_file), (None, 64000));
+
+        // 拡張ID666の時間（1/64000秒単位）が優先される
+        spc_file.header.duration = 10;
+        let mut tag = ExtendedID666::new();
+        tag.intro_length = Some(3 * 64000);
+        tag.loop_length = Some(64000);
+        tag.loop_count = Some(2);
+        tag.end_length = Some(32000);
+        tag.fade_length = Some(64000);
+        spc_file.extended_tag = Some(tag);
+        assert_eq!(
+            play_length_from_tag(&spc_file),
+            (Some((3 * 64000 + 2 * 64000 + 32000) / 2), 32000)
+        );
+
+        // イントロの長さが無ければID666の演奏時間を使う
+        let tag = spc_file.extended_tag.as_mut().unwrap();
+        tag.intro_length = None;
+        tag.fade_length = None;
+        assert_eq!(play_length_from_tag(&spc_file), (Some(320000), 64000));
+    }
+
+    #[test]
+    fn linear_fade_ends_at_zero() {
+        const PLAY_LENGTH: u64 = 32000;
+        const FADE_LENGTH: u64 = 8;
+        let mut renderer = Renderer::new(&bench_spc_file());
+        renderer.set_play_length(Some(PLAY_LENGTH), FADE_LENGTH);
+
+        let mut buffer = std::vec![[0i16; 2]; (PLAY_LENGTH + FADE_LENGTH) as usize + 10];
+        let num_samples = renderer.render(&mut buffer);
+        assert_eq!(num_samples as u64, PLAY_LENGTH + FADE_LENGTH);
+        assert!(renderer.is_finished());
+
+        let fade = &buffer[PLAY_LENGTH as usize..num_samples];
+        assert!(fade.iter().any(|frame| *frame != [0, 0]));
+        assert_eq!(fade[fade.len() - 1], [0, 0]);
+        assert!(buffer[num_samples..].iter().all(|frame| *frame == [0, 0]));
+    }
+}
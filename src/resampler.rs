@@ -0,0 +1,221 @@
+use crate::renderer::OUTPUT_SAMPLING_RATE;
+
+/// 最大タップ数
+const MAX_NUM_TAPS: usize = 32;
+/// 1サンプル間のフィルタ位相の分割数
+const NUM_PHASES: usize = 256;
+/// 出力チャンネル数
+const NUM_CHANNELS: usize = 2;
+
+/// リサンプラの品質
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResamplerQuality {
+    /// 8タップ（低負荷）
+    Low,
+    /// 16タップ
+    Medium,
+    /// 32タップ（高品質）
+    High,
+}
+
+impl ResamplerQuality {
+    /// タップ数
+    fn num_taps(&self) -> usize {
+        match self {
+            ResamplerQuality::Low => 8,
+            ResamplerQuality::Medium => 16,
+            ResamplerQuality::High => MAX_NUM_TAPS,
+        }
+    }
+
+    /// カイザー窓のパラメータ
+    fn kaiser_beta(&self) -> f32 {
+        match self {
+            ResamplerQuality::Low => 5.0,
+            ResamplerQuality::Medium => 7.0,
+            ResamplerQuality::High => 9.0,
+        }
+    }
+
+    /// ナイキスト周波数に対する通過域の比率
+    fn rolloff(&self) -> f32 {
+        match self {
+            ResamplerQuality::Low => 0.85,
+            ResamplerQuality::Medium => 0.9,
+            ResamplerQuality::High => 0.94,
+        }
+    }
+}
+
+/// 窓関数付きsincによるポリフェーズリサンプラ
+pub struct Resampler {
+    /// 入力サンプリングレート
+    input_rate: u32,
+    /// 出力サンプリングレート
+    output_rate: u32,
+    /// タップ数
+    num_taps: usize,
+    /// 位相ごとのフィルタ係数（隣接位相の間は線形補間する）
+    table: [f32; MAX_NUM_TAPS * (NUM_PHASES + 1)],
+    /// 入力履歴（畳み込みで折り返さないよう2周分保持）
+    history: [[f32; NUM_CHANNELS]; 2 * MAX_NUM_TAPS],
+    /// 入力履歴の書き込み位置
+    history_pos: usize,
+    /// 入力サンプル間の出力位置（出力レートを分母とする分子）
+    phase: u32,
+    /// 次の出力までに必要な入力サンプル数
+    pending_input: u32,
+}
+
+/// 0次の第1種変形ベッセル関数
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half = x / 2.0;
+    for k in 1..32 {
+        term *= half / k as f32;
+        sum += term * term;
+        if term * term < sum * 1.0e-9 {
+            break;
+        }
+    }
+    sum
+}
+
+/// 正規化sinc関数
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1.0e-6 {
+        1.0
+    } else {
+        let px = core::f32::consts::PI * x;
+        libm::sinf(px) / px
+    }
+}
+
+impl Resampler {
+    /// DSPの出力(32kHz)から指定レートへのリサンプラを作成
+    pub fn new(output_rate: u32, quality: ResamplerQuality) -> Self {
+        Self::with_input_rate(OUTPUT_SAMPLING_RATE, output_rate, quality)
+    }
+
+    /// 入出力レートを指定してリサンプラを作成
+    pub fn with_input_rate(input_rate: u32, output_rate: u32, quality: ResamplerQuality) -> Self {
+        assert!(input_rate > 0 && output_rate > 0);
+        let num_taps = quality.num_taps();
+        let mut resampler = Self {
+            input_rate,
+            output_rate,
+            num_taps,
+            table: [0.0; MAX_NUM_TAPS * (NUM_PHASES + 1)],
+            history: [[0.0; NUM_CHANNELS]; 2 * MAX_NUM_TAPS],
+            history_pos: 0,
+            phase: 0,
+            // 最初の出力の前に遅延分の入力を読み込む
+            pending_input: (num_taps / 2) as u32,
+        };
+        resampler.build_table(quality);
+        resampler
+    }
+
+    /// フィルタ係数表の作成
+    fn build_table(&mut self, quality: ResamplerQuality) {
+        let num_taps = self.num_taps;
+        let half = (num_taps / 2) as f32;
+        // ダウンサンプリング時は出力のナイキスト周波数まで帯域を制限する
+        let ratio = (self.output_rate as f32 / self.input_rate as f32).min(1.0);
+        let cutoff = quality.rolloff() * ratio;
+        let beta = quality.kaiser_beta();
+        let i0_beta = bessel_i0(beta);
+
+        for phase in 0..=NUM_PHASES {
+            let coefs = &mut self.table[phase * num_taps..(phase + 1) * num_taps];
+            let offset = phase as f32 / NUM_PHASES as f32;
+            for (k, coef) in coefs.iter_mut().enumerate() {
+                // 出力位置から見た入力サンプルの位置
+                let x = k as f32 + 1.0 - half - offset;
+                let r = x / half;
+                let window = if r.abs() >= 1.0 {
+                    0.0
+                } else {
+                    bessel_i0(beta * libm::sqrtf(1.0 - r * r)) / i0_beta
+                };
+                *coef = cutoff * sinc(cutoff * x) * window;
+            }
+            // 直流ゲインを1に正規化
+            let sum: f32 = coefs.iter().sum();
+            if sum != 0.0 {
+                coefs.iter_mut().for_each(|coef| *coef /= sum);
+            }
+        }
+    }
+
+    /// 入力サンプリングレート
+    pub fn input_rate(&self) -> u32 {
+        self.input_rate
+    }
+
+    /// 出力サンプリングレート
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    /// 内部状態をリセット
+    pub fn reset(&mut self) {
+        self.history = [[0.0; NUM_CHANNELS]; 2 * MAX_NUM_TAPS];
+        self.history_pos = 0;
+        self.phase = 0;
+        self.pending_input = (self.num_taps / 2) as u32;
+    }
+
+    /// 入力サンプルを履歴に追加
+    fn push_input(&mut self, frame: [i16; 2]) {
+        let value = [frame[0] as f32, frame[1] as f32];
+        self.history[self.history_pos] = value;
+        self.history[self.history_pos + self.num_taps] = value;
+        self.history_pos = (self.history_pos + 1) % self.num_taps;
+    }
+
+    /// 現在の位相で1サンプル出力し、位相を進める
+    fn compute_output(&mut self) -> [i16; 2] {
+        let num_taps = self.num_taps;
+        // 位相を係数表の位置と補間係数に分解
+        let scaled = self.phase as u64 * NUM_PHASES as u64;
+        let index = (scaled / self.output_rate as u64) as usize;
+        let fraction = (scaled % self.output_rate as u64) as f32 / self.output_rate as f32;
+        let coefs0 = &self.table[index * num_taps..(index + 1) * num_taps];
+        let coefs1 = &self.table[(index + 1) * num_taps..(index + 2) * num_taps];
+        // 古いサンプルから順に並ぶ
+        let history = &self.history[self.history_pos..self.history_pos + num_taps];
+
+        let mut out = [0.0f32; NUM_CHANNELS];
+        for ((sample, &c0), &c1) in history.iter().zip(coefs0).zip(coefs1) {
+            let coef = c0 + (c1 - c0) * fraction;
+            out[0] += sample[0] * coef;
+            out[1] += sample[1] * coef;
+        }
+
+        // 位相を進め、次の出力に必要な入力数を求める
+        let next = self.phase + self.input_rate;
+        self.pending_input = next / self.output_rate;
+        self.phase = next % self.output_rate;
+
+        [
+            libm::roundf(out[0]).clamp(-32768.0, 32767.0) as i16,
+            libm::roundf(out[1]).clamp(-32768.0, 32767.0) as i16,
+        ]
+    }
+
+    /// 必要に応じてsourceから入力を取得しながら、バッファを埋めるまで出力
+    pub fn process<F>(&mut self, out: &mut [[i16; 2]], mut source: F)
+    where
+        F: FnMut() -> [i16; 2],
+    {
+        for frame in out.iter_mut() {
+            while self.pending_input > 0 {
+                self.push_input(source());
+                self.pending_input -= 1;
+            }
+            *frame = self.compute_output();
+        }
+    }
+}
@@ -1,8 +1,26 @@
 use crate::decoder::*;
 use crate::eg::*;
+use crate::snapshot::*;
 use crate::types::*;
 use log::trace;
 
+/// 電源投入時のFLGの値（ソフトリセット・ミュート・エコー書き込み無効）
+const POWER_ON_FLAG: u8 = 0xE0;
+/// ピッチレジスタの有効ビット（14bit）
+const PITCH_MASK: i32 = 0x3FFF;
+/// キーオンしてから発音を開始するまでのサンプル数
+const KEYON_DELAY_SAMPLES: u8 = 5;
+/// FIRフィルタを計算するステップ
+const STEP_ECHO_FIR: u8 = 22;
+/// 出力とエコーフィードバックを計算するステップ
+const STEP_ECHO_OUTPUT: u8 = 26;
+/// エコーバッファに書き込むステップ
+const STEP_ECHO_WRITE: u8 = 29;
+/// ボイスの処理間隔のステップ数
+const STEP_VOICE_INTERVAL: u8 = 3;
+/// グローバルカウンタを更新するステップ
+const STEP_COUNTER: u8 = DSP_STEPS_PER_SAMPLE - 1;
+
 /// ボイス
 #[derive(Copy, Clone, Debug)]
 struct VoiceRegister {
@@ -16,12 +34,14 @@ struct VoiceRegister {
     sample_source: u8,
     /// エンベロープジェネレータ
     eg: EnvelopeGenerator,
-    /// LRゲイン適用前の、最後に出力したサンプル値
+    /// エンベロープ適用後・LRゲイン適用前の、最後に出力したサンプル値（次のボイスのピッチモジュレーションに使用）
     output_sample: i16,
-    /// キーオンされているか
-    keyon: bool,
-    /// キーオフされているか
-    keyoff: bool,
+    /// OUTXレジスタ（エンベロープ適用後の出力の上位8bit。毎サンプル更新）
+    outx: u8,
+    /// ENVXレジスタ（出力に適用したエンベロープの上位7bit。毎サンプル更新）
+    envx: u8,
+    /// キーオン後、発音を開始するまでの残りサンプル数
+    keyon_delay: u8,
     /// 前ボイス出力のピッチモジュレーションをするか
     pitch_mod: bool,
     /// ノイズ有効か
@@ -30,6 +50,18 @@ struct VoiceRegister {
     decoder: Decoder,
     /// ミュートしているか
     ch_mute: bool,
+    /// ENDXフラグ（ENDフラグのブロックに達したらセット、キーオンとENDXへの書き込みでクリア）
+    endx: bool,
+}
+
+/// ボイスごとに分離した出力
+/// 各ボイスはエンベロープ・ボイスボリューム・マスターボリューム適用後の値で、全て足し合わせるとミックス出力とほぼ一致する
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SDSPStems {
+    /// 各ボイスの出力
+    pub voice: [[i16; 2]; 8],
+    /// エコー成分（エコーボリューム適用後）
+    pub echo: [i16; 2],
 }
 
 /// S-DSP
@@ -41,6 +73,8 @@ pub struct SDSP {
     echo_volume: [i8; 2],
     /// フラグ
     flag: u8,
+    /// ソフトリセット中か（全ボイスをキーオフしエンベロープを0にし続ける）
+    soft_reset: bool,
     /// ミュートするか
     mute: bool,
     /// ノイズ周波数
@@ -49,13 +83,19 @@ pub struct SDSP {
     echo_feedback: i8,
     /// エコーバッファに書き込むか
     echo_buffer_write_enable: bool,
+    /// エコーバッファに書き込むか（左チャンネルの書き込み後にラッチした値）
+    echo_buffer_write_latch: bool,
     /// 各チャンネルのエコー有効フラグ
     echo: [bool; 8],
     /// BRRのディレクトリのページ
     brr_dir_page: u8,
-    /// エコーバッファの開始アドレス
+    /// エコーバッファの開始アドレス（ESAレジスタの値）
     echo_buffer_address: usize,
-    /// エコーバッファサイズ
+    /// エコー処理で使用中の開始アドレス（ESAの変更は次のサンプルから反映）
+    echo_buffer_active_address: usize,
+    /// エコーディレイ（EDLレジスタの値）
+    echo_delay: u8,
+    /// エコーバッファサイズ（EDLの変更はバッファを一周したときに反映。0のときは4バイト）
     echo_buffer_size: usize,
     /// エコーバッファ参照位置
     echo_buffer_pos: usize,
@@ -67,8 +107,47 @@ pub struct SDSP {
     fir_buffer_pos: usize,
     /// ゲイン更新用のカウンタ
     global_counter: u16,
+    /// ノイズ生成用のLFSR(15bit)
+    noise_lfsr: u16,
+    /// KONに書き込まれたキーオン（ポーリングで読み込まれると次のポーリングでクリア）
+    new_kon: u8,
+    /// ポーリングで読み込んだキーオン
+    kon: u8,
+    /// ポーリングで読み込んだキーオフ
+    koff: u8,
+    /// KON/KOFFをポーリングしたサンプルか（2サンプルに1回）
+    every_other_sample: bool,
+    /// 書き込まれたレジスタ値（未使用アドレスの読み出し用）
+    register: [u8; 128],
+    /// 厳格モードか
+    strict_mode: bool,
     /// 各チャンネルのボイス
     voice: [VoiceRegister; 8],
+    /// 最後に計算したボイスごとの出力
+    stems: SDSPStems,
+    /// サンプルの補間方式
+    interpolation_mode: InterpolationMode,
+    /// サイクル単位でステップ実行するか
+    cycle_stepped: bool,
+    /// サンプル内で次に処理するステップ
+    step: u8,
+    /// ボイス出力のミックス（飽和加算）
+    main_mix: [i32; 2],
+    /// エコーに入力するボイス出力のミックス（飽和加算）
+    echo_mix: [i32; 2],
+    /// ボイスごとの出力（出力計算時にstemsに反映）
+    voice_stems: [[i16; 2]; 8],
+    /// FIRフィルタ出力
+    fir_out: [i16; 2],
+    /// エコーバッファに書き込む値
+    echo_input: [i16; 2],
+    /// 出力サンプル
+    output: [i16; 2],
+}
+
+/// ミックスへの飽和加算
+fn accumulate(sum: &mut i32, value: i16) {
+    *sum = (*sum + value as i32).clamp(-32768, 32767);
 }
 
 impl VoiceRegister {
@@ -80,44 +159,57 @@ impl VoiceRegister {
             sample_source: 0,
             eg: EnvelopeGenerator::new(),
             output_sample: 0,
-            keyon: false,
-            keyoff: false,
+            outx: 0,
+            envx: 0,
+            keyon_delay: 0,
             pitch_mod: false,
             noise: false,
             decoder: Decoder::new(),
             ch_mute: false,
+            endx: false,
         }
     }
 
     /// 1ステレオサンプル計算
-    fn tick(&mut self, ram: &[u8], global_counter: u16, prev_voice_out: i16) -> [i16; 2] {
-        // キーオンが入ったとき
-        if self.keyon {
-            self.keyon = false;
-            // エンベロープ設定
-            self.eg.keyon();
-            // デコーダ設定
-            self.decoder.keyon(
-                ram,
-                self.brr_dir_address_base + 4 * (self.sample_source as usize),
-            );
-        }
-
-        // キーオフが入ったとき
-        if self.keyoff {
-            // フラグクリア
-            self.keyoff = false;
-        }
-
-        // ピッチ（+モジュレーション）
-        let mut pitch = self.pitch as i32;
-        if self.pitch_mod && !self.noise {
-            let factor = (prev_voice_out >> 4) as i32 + 0x400;
-            pitch = (factor * pitch) >> 10;
-        };
+    fn tick(
+        &mut self,
+        ram: &[u8],
+        global_counter: u16,
+        prev_voice_out: i16,
+        noise_out: i16,
+        interpolation_mode: InterpolationMode,
+    ) -> [i16; 2] {
+        // ピッチ（レジスタの上位2bitは無視される）
+        let mut pitch = (self.pitch as i32) & PITCH_MASK;
+        // ピッチモジュレーション（前のボイスの出力で変調。結果は0から0x7FFFの範囲に収まる）
+        if self.pitch_mod {
+            pitch += (((prev_voice_out >> 5) as i32) * pitch) >> 10;
+        }
+
+        // キーオン後の待機中
+        let keyon_waiting = self.keyon_delay > 0;
+        if keyon_waiting {
+            // 待機の最初のサンプルでBRRの履歴をリセットしてデコードを開始
+            if self.keyon_delay == KEYON_DELAY_SAMPLES {
+                self.endx = false;
+                self.decoder.keyon(
+                    ram,
+                    self.brr_dir_address_base + 4 * (self.sample_source as usize),
+                );
+            }
+            self.keyon_delay -= 1;
+            // 待機中はサンプルを進めない
+            pitch = 0;
+        }
 
         // デコード
-        let mut out = self.decoder.process(ram, pitch as u16);
+        let mut out = self.decoder.process(ram, pitch as u16, interpolation_mode);
+
+        // ENDフラグのブロックを再生し終えたらENDXをセット（ループの有無に関係なく、クリアされるまで保持）
+        if self.decoder.looped {
+            self.decoder.looped = false;
+            self.endx = true;
+        }
 
         // ENDフラグがセットかつループフラグが立っていなければ即時ミュート
         if self.decoder.end {
@@ -126,16 +218,26 @@ impl VoiceRegister {
             }
         }
 
-        // デコード後の出力サンプル更新
-        self.output_sample = out;
+        // ノイズ有効時はデコード結果の代わりにノイズを出力（デコード自体は進める）
+        if self.noise {
+            out = noise_out;
+        }
 
-        // TODO: NON
+        // エンベロープ適用（キーオン後の待機中はエンベロープを動かさず無音）
+        let gain = if keyon_waiting {
+            0
+        } else {
+            self.eg.update(global_counter);
+            self.eg.gain
+        };
+        out = ((((out as i32) * gain) >> 11) & !1) as i16;
 
-        // エンベロープ内部状態更新
-        self.eg.update(global_counter);
+        // 出力サンプル更新
+        self.output_sample = out;
 
-        // エンベロープ適用
-        out = (((out as i32) * self.eg.gain) >> 11) as i16;
+        // OUTX/ENVXの更新（書き込まれた値はここで上書きされる）
+        self.outx = ((out >> 8) & 0xFF) as u8;
+        self.envx = ((gain >> 4) & 0x7F) as u8;
 
         // 左右ボリューム適用
         let lout = ((out as i32) * (self.volume[0] as i32)) >> 7;
@@ -146,43 +248,427 @@ impl VoiceRegister {
 }
 
 impl SDSP {
+    /// 最後のtickで計算したボイスごとの出力
+    pub fn stems(&self) -> &SDSPStems {
+        &self.stems
+    }
+
+    /// サンプルの補間方式を設定（既定は実機と同じガウス補間）
+    pub fn set_interpolation_mode(&mut self, mode: InterpolationMode) {
+        self.interpolation_mode = mode;
+    }
+
+    /// サンプルの補間方式
+    pub fn interpolation_mode(&self) -> InterpolationMode {
+        self.interpolation_mode
+    }
+
+    /// サイクル単位でのステップ実行の有効/無効を設定
+    /// 有効にすると1サンプルの処理を32ステップに分けて行い、
+    /// サンプルの途中で書き込まれたレジスタは書き込まれたステップ以降の処理に反映される
+    /// 実機のパイプライン（ボイスごとのV1-V9ステージ）は再現しない近似で、実機とのビット一致は保証しない
+    pub fn set_cycle_stepped(&mut self, enable: bool) {
+        self.cycle_stepped = enable;
+    }
+
+    /// サイクル単位でステップ実行しているか
+    pub fn is_cycle_stepped(&self) -> bool {
+        self.cycle_stepped
+    }
+
+    /// 電源投入時の状態にリセット
+    /// FLGは$E0（ソフトリセット・ミュート・エコー書き込み無効）、他のレジスタと内部状態は0になる
+    /// 厳格モード・補間方式・ステップ実行の設定は保持する
+    pub fn power_on_reset(&mut self) {
+        let mut dsp = <Self as SPCDSP>::new();
+        dsp.strict_mode = self.strict_mode;
+        dsp.interpolation_mode = self.interpolation_mode;
+        dsp.cycle_stepped = self.cycle_stepped;
+        *self = dsp;
+    }
+
+    /// FLGの設定
+    fn set_flag(&mut self, value: u8) {
+        self.soft_reset = (value & 0x80) != 0;
+        self.mute = (value & 0x40) != 0;
+        self.echo_buffer_write_enable = (value & 0x20) == 0; // ~ECEN
+        self.noise_clock = value & 0x1F;
+        // 読まれる可能性があるので、値としては保持しておく
+        self.flag = value;
+        self.register[DSP_ADDRESS_FLG as usize] = value;
+    }
+
+    /// 1ボイス処理してミックスに加算
+    fn run_voice(&mut self, ram: &[u8], ch: usize) {
+        // ピッチモジュレーションには前のボイスの出力を使う
+        let prev_voice_out = if ch == 0 {
+            0
+        } else {
+            self.voice[ch - 1].output_sample
+        };
+        // ノイズは全チャンネルで共有（15bitの値を16bit幅に拡張して出力）
+        let noise_out = (self.noise_lfsr << 1) as i16;
+        let vout = self.voice[ch].tick(
+            ram,
+            self.global_counter,
+            prev_voice_out,
+            noise_out,
+            self.interpolation_mode,
+        );
+        if !self.voice[ch].ch_mute {
+            accumulate(&mut self.main_mix[0], vout[0]);
+            accumulate(&mut self.main_mix[1], vout[1]);
+            if self.echo[ch] {
+                accumulate(&mut self.echo_mix[0], vout[0]);
+                accumulate(&mut self.echo_mix[1], vout[1]);
+            }
+            for (stem, (&v, &volume)) in self.voice_stems[ch]
+                .iter_mut()
+                .zip(vout.iter().zip(self.volume.iter()))
+            {
+                *stem = (((v as i32) * (volume as i32)) >> 7) as i16;
+            }
+        } else {
+            self.voice_stems[ch] = [0; 2];
+        }
+        // ソフトリセット中はキーオフしてエンベロープを0にする（出力の計算後）
+        if self.soft_reset {
+            self.voice[ch].eg.mute();
+        }
+        // ポーリングしたサンプルではKOFF・KONの順に反映（出力の計算後）
+        if self.every_other_sample {
+            let bit = 1 << ch;
+            if (self.koff & bit) != 0 {
+                self.voice[ch].eg.keyoff();
+            }
+            if (self.kon & bit) != 0 {
+                self.voice[ch].keyon_delay = KEYON_DELAY_SAMPLES;
+                self.voice[ch].eg.keyon();
+            }
+        }
+    }
+
+    /// KON/KOFFのポーリング（2サンプルに1回）
+    fn poll_keys(&mut self) {
+        self.every_other_sample = !self.every_other_sample;
+        if self.every_other_sample {
+            // 前回のポーリングで読み込んだキーオンはクリアされる
+            self.new_kon &= !self.kon;
+            self.kon = self.new_kon;
+            self.koff = self.register[DSP_ADDRESS_KOFF as usize];
+        }
+    }
+
+    /// 出力とエコーフィードバックの計算
+    fn mix_output(&mut self) {
+        // マスターボリューム適用・エコー成分加算
+        for ch in 0..2 {
+            let echo_out =
+                (((self.fir_out[ch] as i32) * (self.echo_volume[ch] as i32)) >> 7) as i16;
+            let main_out = ((self.main_mix[ch] * (self.volume[ch] as i32)) >> 7) as i16;
+            self.output[ch] = (main_out as i32 + echo_out as i32).clamp(-32768, 32767) as i16;
+            self.stems.echo[ch] = echo_out;
+        }
+        self.stems.voice = self.voice_stems;
+        // フィードバック成分加算（飽和させ、最下位bitは捨てられる）
+        for ch in 0..2 {
+            let feedback = (((self.fir_out[ch] as i32) * (self.echo_feedback as i32)) >> 7) as i16;
+            self.echo_input[ch] =
+                ((self.echo_mix[ch] + feedback as i32).clamp(-32768, 32767) & !1) as i16;
+        }
+        // ミュートならば無音
+        if self.mute {
+            self.output = [0; 2];
+            self.stems = SDSPStems::default();
+        }
+        // 次のサンプルのミックスを開始
+        self.main_mix = [0; 2];
+        self.echo_mix = [0; 2];
+    }
+
+    /// グローバルカウンタの更新
+    fn update_global_counter(&mut self) {
+        if self.global_counter == 0 {
+            self.global_counter = 0x77FF;
+        }
+        self.global_counter -= 1;
+    }
+
+    /// サンプル内の1ステップを処理
+    /// 一括処理の処理順を32ステップに割り当てた近似で、ステップ0でノイズ更新とKON/KOFFのポーリングを行った後、
+    /// ボイス0-7をそれぞれ1ステップでまとめて3ステップおきに処理する
+    /// （実機では各ボイスの処理が複数ステップにまたがり、KON/KOFFは前のサンプルのステップ30でポーリングされる）
+    fn run_step(&mut self, ram: &mut [u8], step: u8) {
+        match step {
+            0 => {
+                self.update_noise();
+                self.poll_keys();
+                self.run_voice(ram, 0);
+            }
+            3 | 6 | 9 | 12 | 15 | 18 | 21 => {
+                self.run_voice(ram, (step / STEP_VOICE_INTERVAL) as usize)
+            }
+            STEP_ECHO_FIR => self.fir_out = self.compute_fir(ram),
+            STEP_ECHO_OUTPUT => self.mix_output(),
+            STEP_ECHO_WRITE => {
+                let echo_input = self.echo_input;
+                self.put_echo_buffer(ram, &echo_input);
+            }
+            STEP_COUNTER => self.update_global_counter(),
+            _ => {}
+        }
+    }
+
+    /// 指定ステップの直前まで処理を進める
+    fn run_until(&mut self, ram: &mut [u8], end_step: u8) {
+        while self.step < end_step {
+            self.run_step(ram, self.step);
+            self.step += 1;
+        }
+    }
+
+    /// 未使用アドレスのレジスタ読み込み
+    fn read_unused_register(&self, address: u8) -> Result<u8, SPCError> {
+        if self.strict_mode {
+            return Err(SPCError::UnsupportedDSPAddress { address });
+        }
+        // 実機では書き込んだ値がそのまま読み出せる
+        Ok(self.register[(address & 0x7F) as usize])
+    }
+
+    /// ノイズ生成器の更新
+    fn update_noise(&mut self) {
+        // FLGの下位5bitをレートとしてLFSRを進める
+        if check_counter_event(self.global_counter, self.noise_clock) {
+            let feedback = (self.noise_lfsr << 13) ^ (self.noise_lfsr << 14);
+            self.noise_lfsr = (feedback & 0x4000) ^ (self.noise_lfsr >> 1);
+        }
+    }
+
+    /// エコーバッファの指定位置のRAMアドレス（16bitで折り返す）
+    fn echo_ram_address(&self, offset: usize) -> usize {
+        (self.echo_buffer_active_address + self.echo_buffer_pos + offset) & 0xFFFF
+    }
+
     /// FIRフィルタ出力計算
     fn compute_fir(&mut self, ram: &[u8]) -> [i16; 2] {
-        // エコーバッファのアドレス
-        let echo_buffer_addr = self.echo_buffer_address + self.echo_buffer_pos;
-
         // FIRバッファ更新
         for ch in 0..2 {
-            let buf = (((ram[echo_buffer_addr + 2 * ch + 1] as i8) as i16) << 8)
-                | (ram[echo_buffer_addr + 2 * ch + 0] as i16);
+            let buf = (((ram[self.echo_ram_address(2 * ch + 1)] as i8) as i16) << 8)
+                | (ram[self.echo_ram_address(2 * ch)] as i16);
             self.fir_buffer[ch][self.fir_buffer_pos] = buf >> 1; // 下位1bitは捨てられる
         }
 
         // FIRフィルタ計算
         let mut out = [0; 2];
         for ch in 0..2 {
-            for i in 0..8 {
+            let tap = |i: usize| {
                 let buf =
                     self.fir_buffer[ch][(self.fir_buffer_pos.wrapping_sub(7 - i)) & 0x7] as i32;
-                out[ch] += (buf * (self.fir_coef[i] as i32)) >> 6;
+                (buf * (self.fir_coef[i] as i32)) >> 6
+            };
+            // 最初の7タップの和は16bitで折り返す
+            let mut sum = 0;
+            for i in 0..7 {
+                sum += tap(i);
             }
+            let sum = sum as i16 as i32;
+            // 最後のタップを加えた結果は飽和させ、最下位bitは捨てられる
+            out[ch] = ((sum + (tap(7) as i16 as i32)).clamp(-32768, 32767) & !1) as i16;
         }
         self.fir_buffer_pos = (self.fir_buffer_pos + 1) & 0x7;
 
-        [out[0] as i16, out[1] as i16]
+        out
     }
 
     /// エコーバッファの更新
-    fn put_echo_buffer(&mut self, ram: &mut [u8], echo_in: &[i32; 2]) {
+    fn put_echo_buffer(&mut self, ram: &mut [u8], echo_in: &[i16; 2]) {
         // リングバッファ書き込み
-        if self.echo_buffer_write_enable {
-            let echo_buffer_addr = self.echo_buffer_address + self.echo_buffer_pos;
-            ram[echo_buffer_addr + 0] = ((echo_in[0] >> 0) & 0xFF) as u8;
-            ram[echo_buffer_addr + 1] = ((echo_in[0] >> 8) & 0xFF) as u8;
-            ram[echo_buffer_addr + 2] = ((echo_in[1] >> 0) & 0xFF) as u8;
-            ram[echo_buffer_addr + 3] = ((echo_in[1] >> 8) & 0xFF) as u8;
+        // 書き込み可否は左チャンネルを書き込んだ後にラッチされるため、FLGの変更は右チャンネルから反映
+        for ch in 0..2 {
+            if self.echo_buffer_write_latch {
+                ram[self.echo_ram_address(2 * ch)] = (echo_in[ch] & 0xFF) as u8;
+                ram[self.echo_ram_address(2 * ch + 1)] = ((echo_in[ch] >> 8) & 0xFF) as u8;
+            }
+            if ch == 0 {
+                self.echo_buffer_write_latch = self.echo_buffer_write_enable;
+            }
         }
-        self.echo_buffer_pos = (self.echo_buffer_pos + 4) % self.echo_buffer_size;
+        // EDLはバッファの先頭に戻ったときのみ読み込まれる
+        if self.echo_buffer_pos == 0 {
+            self.echo_buffer_size = ((self.echo_delay & 0x0F) as usize) << 11;
+        }
+        // EDL=0のときは4バイトのバッファとして振る舞う
+        self.echo_buffer_pos += 4;
+        if self.echo_buffer_pos >= self.echo_buffer_size {
+            self.echo_buffer_pos = 0;
+        }
+        // ESAは次のサンプルから反映
+        self.echo_buffer_active_address = self.echo_buffer_address;
+    }
+}
+
+/// スナップショット内でSDSPの状態を示す識別子
+const SDSP_SNAPSHOT_TAG: [u8; 4] = *b"SDSP";
+
+impl Snapshot for VoiceRegister {
+    fn save_state(&self, writer: &mut SnapshotWriter) -> Result<(), SnapshotError> {
+        writer.write_i8(self.volume[0])?;
+        writer.write_i8(self.volume[1])?;
+        writer.write_u16(self.pitch)?;
+        writer.write_usize(self.brr_dir_address_base)?;
+        writer.write_u8(self.sample_source)?;
+        self.eg.save_state(writer)?;
+        writer.write_i16(self.output_sample)?;
+        writer.write_u8(self.outx)?;
+        writer.write_u8(self.envx)?;
+        writer.write_u8(self.keyon_delay)?;
+        writer.write_bool(self.pitch_mod)?;
+        writer.write_bool(self.noise)?;
+        self.decoder.save_state(writer)?;
+        writer.write_bool(self.ch_mute)?;
+        writer.write_bool(self.endx)
+    }
+
+    fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.volume[0] = reader.read_i8()?;
+        self.volume[1] = reader.read_i8()?;
+        self.pitch = reader.read_u16()?;
+        self.brr_dir_address_base = reader.read_usize()?;
+        self.sample_source = reader.read_u8()?;
+        self.eg.load_state(reader)?;
+        self.output_sample = reader.read_i16()?;
+        self.outx = reader.read_u8()?;
+        self.envx = reader.read_u8()?;
+        self.keyon_delay = reader.read_u8()?;
+        self.pitch_mod = reader.read_bool()?;
+        self.noise = reader.read_bool()?;
+        self.decoder.load_state(reader)?;
+        self.ch_mute = reader.read_bool()?;
+        self.endx = reader.read_bool()?;
+        if self.brr_dir_address_base > 0xFF00 || self.keyon_delay > KEYON_DELAY_SAMPLES {
+            return Err(SnapshotError::InvalidData);
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot for SDSP {
+    fn save_state(&self, writer: &mut SnapshotWriter) -> Result<(), SnapshotError> {
+        writer.write_bytes(&SDSP_SNAPSHOT_TAG)?;
+        writer.write_i8(self.volume[0])?;
+        writer.write_i8(self.volume[1])?;
+        writer.write_i8(self.echo_volume[0])?;
+        writer.write_i8(self.echo_volume[1])?;
+        writer.write_u8(self.flag)?;
+        writer.write_bool(self.mute)?;
+        writer.write_u8(self.noise_clock)?;
+        writer.write_i8(self.echo_feedback)?;
+        writer.write_bool(self.soft_reset)?;
+        writer.write_bool(self.echo_buffer_write_enable)?;
+        writer.write_bool(self.echo_buffer_write_latch)?;
+        for echo in self.echo.iter() {
+            writer.write_bool(*echo)?;
+        }
+        writer.write_u8(self.brr_dir_page)?;
+        writer.write_usize(self.echo_buffer_address)?;
+        writer.write_usize(self.echo_buffer_active_address)?;
+        writer.write_u8(self.echo_delay)?;
+        writer.write_usize(self.echo_buffer_size)?;
+        writer.write_usize(self.echo_buffer_pos)?;
+        for coef in self.fir_coef.iter() {
+            writer.write_i8(*coef)?;
+        }
+        for ch in 0..2 {
+            for sample in self.fir_buffer[ch].iter() {
+                writer.write_i16(*sample)?;
+            }
+        }
+        writer.write_usize(self.fir_buffer_pos)?;
+        writer.write_u16(self.global_counter)?;
+        writer.write_u16(self.noise_lfsr)?;
+        writer.write_u8(self.new_kon)?;
+        writer.write_u8(self.kon)?;
+        writer.write_u8(self.koff)?;
+        writer.write_bool(self.every_other_sample)?;
+        writer.write_bytes(&self.register)?;
+        for voice in self.voice.iter() {
+            voice.save_state(writer)?;
+        }
+        writer.write_u8(self.step)?;
+        for ch in 0..2 {
+            writer.write_i32(self.main_mix[ch])?;
+            writer.write_i32(self.echo_mix[ch])?;
+            writer.write_i16(self.fir_out[ch])?;
+            writer.write_i16(self.echo_input[ch])?;
+            writer.write_i16(self.output[ch])?;
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.expect_tag(&SDSP_SNAPSHOT_TAG)?;
+        self.volume[0] = reader.read_i8()?;
+        self.volume[1] = reader.read_i8()?;
+        self.echo_volume[0] = reader.read_i8()?;
+        self.echo_volume[1] = reader.read_i8()?;
+        self.flag = reader.read_u8()?;
+        self.mute = reader.read_bool()?;
+        self.noise_clock = reader.read_u8()?;
+        self.echo_feedback = reader.read_i8()?;
+        self.soft_reset = reader.read_bool()?;
+        self.echo_buffer_write_enable = reader.read_bool()?;
+        self.echo_buffer_write_latch = reader.read_bool()?;
+        for echo in self.echo.iter_mut() {
+            *echo = reader.read_bool()?;
+        }
+        self.brr_dir_page = reader.read_u8()?;
+        self.echo_buffer_address = reader.read_usize()?;
+        self.echo_buffer_active_address = reader.read_usize()?;
+        self.echo_delay = reader.read_u8()?;
+        self.echo_buffer_size = reader.read_usize()?;
+        self.echo_buffer_pos = reader.read_usize()?;
+        for coef in self.fir_coef.iter_mut() {
+            *coef = reader.read_i8()?;
+        }
+        for ch in 0..2 {
+            for sample in self.fir_buffer[ch].iter_mut() {
+                *sample = reader.read_i16()?;
+            }
+        }
+        self.fir_buffer_pos = reader.read_usize()?;
+        self.global_counter = reader.read_u16()?;
+        self.noise_lfsr = reader.read_u16()?;
+        self.new_kon = reader.read_u8()?;
+        self.kon = reader.read_u8()?;
+        self.koff = reader.read_u8()?;
+        self.every_other_sample = reader.read_bool()?;
+        reader.read_bytes(&mut self.register)?;
+        for voice in self.voice.iter_mut() {
+            voice.load_state(reader)?;
+        }
+        self.step = reader.read_u8()?;
+        for ch in 0..2 {
+            self.main_mix[ch] = reader.read_i32()?;
+            self.echo_mix[ch] = reader.read_i32()?;
+            self.fir_out[ch] = reader.read_i16()?;
+            self.echo_input[ch] = reader.read_i16()?;
+            self.output[ch] = reader.read_i16()?;
+        }
+        // 範囲外の値はエミュレーション中にパニックを起こすため拒否
+        if self.noise_clock > 0x1F
+            || self.echo_buffer_address > 0xFF00
+            || self.echo_buffer_active_address > 0xFF00
+            || self.echo_buffer_size > 0x7800
+            || (self.echo_buffer_pos & 0x3) != 0
+            || self.echo_buffer_pos >= self.echo_buffer_size.max(4)
+            || self.fir_buffer_pos > 0x7
+            || self.global_counter >= 0x7800
+            || self.step >= DSP_STEPS_PER_SAMPLE
+        {
+            return Err(SnapshotError::InvalidData);
+        }
+        Ok(())
     }
 }
 
@@ -190,18 +676,23 @@ impl SPCDSP for SDSP {
     type Output = [i16; 2];
 
     /// コンストラクタ
+    /// 電源投入時の状態で作成
     fn new() -> Self {
-        Self {
+        let mut dsp = Self {
             volume: [0; 2],
             echo_volume: [0; 2],
             flag: 0,
+            soft_reset: false,
             mute: false,
             echo_buffer_write_enable: false,
+            echo_buffer_write_latch: false,
             noise_clock: 0,
             echo_feedback: 0,
             echo: [false; 8],
             brr_dir_page: 0,
             echo_buffer_address: 0,
+            echo_buffer_active_address: 0,
+            echo_delay: 0,
             echo_buffer_size: 0,
             echo_buffer_pos: 0,
             fir_coef: [0; 8],
@@ -209,36 +700,69 @@ impl SPCDSP for SDSP {
             fir_buffer_pos: 0,
             voice: [VoiceRegister::new(); 8],
             global_counter: 0,
-        }
+            noise_lfsr: 0x4000,
+            new_kon: 0,
+            kon: 0,
+            koff: 0,
+            every_other_sample: false,
+            register: [0; 128],
+            strict_mode: false,
+            stems: SDSPStems::default(),
+            interpolation_mode: InterpolationMode::Gaussian,
+            cycle_stepped: false,
+            step: 0,
+            main_mix: [0; 2],
+            echo_mix: [0; 2],
+            voice_stems: [[0; 2]; 8],
+            fir_out: [0; 2],
+            echo_input: [0; 2],
+            output: [0; 2],
+        };
+        dsp.set_flag(POWER_ON_FLAG);
+        dsp.echo_buffer_write_latch = dsp.echo_buffer_write_enable;
+        dsp
     }
 
     /// 128バイトメモリから初期化
     fn initialize(&mut self, ram: &mut [u8], dsp_register: &[u8; 128]) {
-        // DIRは先に設定（初期状態でKONがある場合にアドレスを正しくするため）
-        self.write_register(ram, DSP_ADDRESS_DIR, dsp_register[DSP_ADDRESS_DIR as usize]);
+        // DIRは先に設定（SRCNの設定でデコードアドレスを正しくするため）
+        let _ = self.write_register(ram, DSP_ADDRESS_DIR, dsp_register[DSP_ADDRESS_DIR as usize]);
 
         // すべてのレジスタを設定
+        // KONはラッチされ、最初のポーリングでキーオンされる
         for i in 0..128 {
-            self.write_register(ram, i, dsp_register[i as usize]);
+            let _ = self.write_register(ram, i, dsp_register[i as usize]);
         }
 
         // ENDXは最後に直接設定（通常の設定処理ではすべてクリアされるため）
         let endx = dsp_register[DSP_ADDRESS_ENDX as usize];
         for ch in 0..8 {
-            self.voice[ch].decoder.end = ((endx >> ch) & 0x1) != 0;
+            self.voice[ch].endx = ((endx >> ch) & 0x1) != 0;
         }
 
+        // エコーバッファの状態を先頭から開始
+        self.echo_buffer_active_address = self.echo_buffer_address;
+        self.echo_buffer_size = ((self.echo_delay & 0x0F) as usize) << 11;
+        self.echo_buffer_pos = 0;
+        self.echo_buffer_write_latch = self.echo_buffer_write_enable;
+
         // エコーバッファの内容をクリア（初期のRAMに信号が残っている場合がある）
-        if self.echo_buffer_address != 0 && self.echo_buffer_size != 0 {
-            for i in 0..self.echo_buffer_size {
-                ram[self.echo_buffer_address + i] = 0;
+        if self.echo_buffer_address != 0 {
+            for i in 0..self.echo_buffer_size.max(4) {
+                ram[(self.echo_buffer_address + i) & 0xFFFF] = 0;
             }
         }
     }
 
+    /// 厳格モードの設定
+    fn set_strict_mode(&mut self, strict: bool) {
+        self.strict_mode = strict;
+    }
+
     /// DSPレジスタの書き込み処理
-    fn write_register(&mut self, ram: &[u8], address: u8, value: u8) {
+    fn write_register(&mut self, ram: &[u8], address: u8, value: u8) -> Result<(), SPCError> {
         trace!("DSPW: {:02X} <- {:02X}", address, value);
+        self.register[(address & 0x7F) as usize] = value;
         match address & 0x7F {
             DSP_ADDRESS_MVOLL => {
                 self.volume[0] = value as i8;
@@ -253,40 +777,28 @@ impl SPCDSP for SDSP {
                 self.echo_volume[1] = value as i8;
             }
             DSP_ADDRESS_KON => {
-                for ch in 0..8 {
-                    self.voice[ch].keyon = ((value >> ch) & 0x1) != 0;
-                }
+                // 次のポーリングで読み込まれる
+                self.new_kon = value;
             }
             DSP_ADDRESS_KOFF => {
-                for ch in 0..8 {
-                    let keyoff = ((value >> ch) & 0x1) != 0;
-                    self.voice[ch].keyoff = keyoff;
-                    // サンプル処理する前にKOFFがクリアされることがあるため、即時に反映
-                    if keyoff {
-                        self.voice[ch].eg.keyoff();
-                    }
-                }
+                // ポーリング時のレジスタ値が反映される
             }
             DSP_ADDRESS_FLG => {
-                // FIXME: RESETは無視
-                self.mute = (value & 0x40) != 0;
-                self.echo_buffer_write_enable = (value & 0x20) == 0; // ~ECEN
-                self.noise_clock = value & 0x1F;
-                // 読まれる可能性があるので、値としては保持しておく
-                self.flag = value;
+                self.set_flag(value);
             }
             DSP_ADDRESS_ENDX => {
                 // 注意：書かれた値に関係なくすべてのフラグをクリア
                 for ch in 0..8 {
-                    self.voice[ch].decoder.end = false;
+                    self.voice[ch].endx = false;
                 }
             }
             DSP_ADDRESS_EFB => {
                 self.echo_feedback = value as i8;
             }
             DSP_ADDRESS_PMON => {
+                // ボイス0には前のボイスがないためbit0は無視される
+                self.voice[0].pitch_mod = false;
                 for ch in 1..8 {
-                    /* NOTE! 0は無効 */
                     self.voice[ch].pitch_mod = ((value >> ch) & 0x1) != 0;
                 }
             }
@@ -310,11 +822,7 @@ impl SPCDSP for SDSP {
                 self.echo_buffer_address = (value as usize) << 8;
             }
             DSP_ADDRESS_EDL => {
-                self.echo_buffer_size = if (value & 0x0F) == 0 {
-                    4
-                } else {
-                    ((value & 0x0F) as usize) << 11
-                };
+                self.echo_delay = value;
             }
             DSP_ADDRESS_FIR0 | DSP_ADDRESS_FIR1 | DSP_ADDRESS_FIR2 | DSP_ADDRESS_FIR3 | DSP_ADDRESS_FIR4 | DSP_ADDRESS_FIR5 | DSP_ADDRESS_FIR6 | DSP_ADDRESS_FIR7 => {
                 let index = address >> 4;
@@ -359,12 +867,12 @@ impl SPCDSP for SDSP {
                         self.voice[ch].eg.set_gain(value);
                     }
                     DSP_ADDRESS_V0ENVX => {
-                        // 書き込みは無視される（読み取り用レジスタ）
-                        // 実際は書き込んで操作できるが、そのような使い方は考慮外とする
+                        // 書き込んだ値は次のサンプル処理までは読み出せる（エンベロープには影響しない）
+                        self.voice[ch].envx = value;
                     }
                     DSP_ADDRESS_V0OUTX => {
-                        // 書き込めるけど意味はない（読み取り用レジスタ）
-                        self.voice[ch].output_sample = (value as i16) << 8;
+                        // 書き込んだ値は次のサンプル処理までは読み出せる（出力には影響しない）
+                        self.voice[ch].outx = value;
                     }
                     _ => {
                         // 他のアドレスへの書き込みは効果なし
@@ -375,45 +883,26 @@ impl SPCDSP for SDSP {
                 // 他のアドレスへの書き込みは効果なし
             }
         }
+        Ok(())
     }
 
     /// DSPレジスタの読み込み処理
-    fn read_register(&self, _ram: &[u8], address: u8) -> u8 {
+    fn read_register(&self, _ram: &[u8], address: u8) -> Result<u8, SPCError> {
         trace!("DSPR: {:02X}", address);
         // 80-FFの読み込みは00-7Fと同等に扱われる
-        match address & 0x7F {
+        let value = match address & 0x7F {
             DSP_ADDRESS_MVOLL => self.volume[0] as u8,
             DSP_ADDRESS_MVOLR => self.volume[1] as u8,
             DSP_ADDRESS_EVOLL => self.echo_volume[0] as u8,
             DSP_ADDRESS_EVOLR => self.echo_volume[1] as u8,
-            DSP_ADDRESS_KON => {
-                let mut ret = 0;
-                let mut bit = 1;
-                for ch in 0..8 {
-                    if self.voice[ch].keyon {
-                        ret |= bit;
-                    }
-                    bit <<= 1;
-                }
-                ret
-            }
-            DSP_ADDRESS_KOFF => {
-                let mut ret = 0;
-                let mut bit = 1;
-                for ch in 0..8 {
-                    if self.voice[ch].keyoff {
-                        ret |= bit;
-                    }
-                    bit <<= 1;
-                }
-                ret
-            }
+            // 書き込んだ値がそのまま読み出せる
+            DSP_ADDRESS_KON | DSP_ADDRESS_KOFF => self.register[(address & 0x7F) as usize],
             DSP_ADDRESS_FLG => self.flag,
             DSP_ADDRESS_ENDX => {
                 let mut ret = 0;
                 let mut bit = 1;
                 for ch in 0..8 {
-                    if self.voice[ch].decoder.end {
+                    if self.voice[ch].endx {
                         ret |= bit;
                     }
                     bit <<= 1;
@@ -421,18 +910,8 @@ impl SPCDSP for SDSP {
                 ret
             }
             DSP_ADDRESS_EFB => self.echo_feedback as u8,
-            DSP_ADDRESS_PMON => {
-                let mut ret = 0;
-                let mut bit = 1;
-                for ch in 1..8 {
-                    /* NOTE! ch==0は常に無効 */
-                    if self.voice[ch].pitch_mod {
-                        ret |= bit;
-                    }
-                    bit <<= 1;
-                }
-                ret
-            }
+            // 書き込んだ値がそのまま読み出せる（bit0も含む）
+            DSP_ADDRESS_PMON => self.register[DSP_ADDRESS_PMON as usize],
             DSP_ADDRESS_NON => {
                 let mut ret = 0;
                 let mut bit = 1;
@@ -457,7 +936,7 @@ impl SPCDSP for SDSP {
             }
             DSP_ADDRESS_DIR => self.brr_dir_page,
             DSP_ADDRESS_ESA => ((self.echo_buffer_address >> 8) & 0xFF) as u8,
-            DSP_ADDRESS_EDL => ((self.echo_buffer_size >> 11) & 0xFF) as u8,
+            DSP_ADDRESS_EDL => self.echo_delay,
             DSP_ADDRESS_FIR0 | DSP_ADDRESS_FIR1 | DSP_ADDRESS_FIR2 | DSP_ADDRESS_FIR3
             | DSP_ADDRESS_FIR4 | DSP_ADDRESS_FIR5 | DSP_ADDRESS_FIR6 | DSP_ADDRESS_FIR7 => {
                 let index = address >> 4;
@@ -485,62 +964,117 @@ impl SPCDSP for SDSP {
                     DSP_ADDRESS_V0ADSR1 => self.voice[ch].eg.get_adsr1(),
                     DSP_ADDRESS_V0ADSR2 => self.voice[ch].eg.get_adsr2(),
                     DSP_ADDRESS_V0GAIN => self.voice[ch].eg.get_gain(),
-                    DSP_ADDRESS_V0ENVX => ((self.voice[ch].eg.gain >> 4) & 0xFF) as u8,
-                    DSP_ADDRESS_V0OUTX => ((self.voice[ch].output_sample >> 8) & 0xFF) as u8,
-                    _ => {
-                        panic!("Unsupported DSP address!");
-                    }
+                    DSP_ADDRESS_V0ENVX => self.voice[ch].envx,
+                    DSP_ADDRESS_V0OUTX => self.voice[ch].outx,
+                    _ => self.read_unused_register(address)?,
                 }
             }
-            _ => {
-                panic!("Unsupported DSP address!");
-            }
-        }
+            _ => self.read_unused_register(address)?,
+        };
+        Ok(value)
     }
 
     /// ステレオサンプル計算処理
     fn tick(&mut self, ram: &mut [u8]) -> Option<[i16; 2]> {
-        let mut out = [0i32; 2];
-        let mut echo_in = [0i32; 2];
-        let mut prev_voice_out = 0;
-        // 全チャンネルの出力をミックス
-        for ch in 0..8 {
-            let vout = self.voice[ch].tick(ram, self.global_counter, prev_voice_out);
-            if !self.voice[ch].ch_mute {
-                out[0] += vout[0] as i32;
-                out[1] += vout[1] as i32;
-                if self.echo[ch] {
-                    echo_in[0] += vout[0] as i32;
-                    echo_in[1] += vout[1] as i32;
-                }
+        if self.cycle_stepped || self.step != 0 {
+            // 残りのステップを処理
+            self.run_until(ram, DSP_STEPS_PER_SAMPLE);
+            self.step = 0;
+        } else {
+            // ノイズ更新
+            self.update_noise();
+            // KON/KOFFのポーリング
+            self.poll_keys();
+            // 全チャンネルの出力をミックス
+            for ch in 0..8 {
+                self.run_voice(ram, ch);
             }
-            prev_voice_out = self.voice[ch].output_sample;
-        }
-        // エコー成分計算
-        let fir_out = self.compute_fir(ram);
-        // マスターボリューム適用・エコー成分加算
-        for ch in 0..2 {
-            out[ch] = (out[ch] * (self.volume[ch] as i32)) >> 7;
-            out[ch] += ((fir_out[ch] as i32) * (self.echo_volume[ch] as i32)) >> 7;
+            // エコー成分計算
+            self.fir_out = self.compute_fir(ram);
+            // 出力・フィードバック成分計算
+            self.mix_output();
+            // エコーバッファ更新
+            let echo_input = self.echo_input;
+            self.put_echo_buffer(ram, &echo_input);
+            // グローバルカウンタの更新
+            self.update_global_counter();
         }
-        // フィードバック成分加算
-        for ch in 0..2 {
-            echo_in[ch] += ((fir_out[ch] as i32) * (self.echo_feedback as i32)) >> 7;
+
+        Some(self.output)
+    }
+
+    /// 指定ステップの直前まで処理を進める（サイクル単位でのステップ実行時のみ）
+    fn synchronize(&mut self, ram: &mut [u8], step: u8) {
+        if self.cycle_stepped {
+            self.run_until(ram, step.min(DSP_STEPS_PER_SAMPLE - 1));
         }
-        // エコーバッファ更新
-        self.put_echo_buffer(ram, &echo_in);
-        // ミュートならば無音
-        if self.mute {
-            for ch in 0..2 {
-                out[ch] = 0;
-            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spc_file::parse_spc_file;
+
+    /// サンプル途中での書き込みがなければステップ実行の有無で出力が一致する
+    #[test]
+    fn cycle_stepped_matches_batch_output() {
+        let data = std::fs::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/benches/data/Eyes on Me! [2 = Original] [1 = OG Echo].spc"
+        ))
+        .unwrap();
+        let spc_file = parse_spc_file(&data).unwrap();
+
+        let mut batch_ram = spc_file.ram;
+        let mut stepped_ram = spc_file.ram;
+        let mut batch = SDSP::new();
+        let mut stepped = SDSP::new();
+        batch.initialize(&mut batch_ram, &spc_file.dsp_register);
+        stepped.initialize(&mut stepped_ram, &spc_file.dsp_register);
+        stepped.set_cycle_stepped(true);
+
+        // 全ボイスを固定ゲインで発音させ、ピッチモジュレーションとエコーも有効にする
+        let mut writes = std::vec::Vec::new();
+        for ch in 0..8u8 {
+            let base = ch << 4;
+            writes.extend_from_slice(&[
+                (base, 0x30),
+                (base + 1, 0x30),
+                (base + 2, 0x00),
+                (base + 3, 0x08 + ch),
+                (base + 4, ch),
+                (base + 5, 0x00),
+                (base + 7, 0x7F),
+            ]);
         }
-        // グローバルカウンタの更新
-        if self.global_counter == 0 {
-            self.global_counter = 0x77FF;
+        writes.extend_from_slice(&[
+            (DSP_ADDRESS_PMON, 0x0A),
+            (DSP_ADDRESS_EON, 0xFF),
+            (DSP_ADDRESS_ESA, 0xE0),
+            (DSP_ADDRESS_EDL, 0x01),
+            (DSP_ADDRESS_EFB, 0x40),
+            (DSP_ADDRESS_EVOLL, 0x20),
+            (DSP_ADDRESS_EVOLR, 0x20),
+            (DSP_ADDRESS_FIR0, 0x7F),
+            (DSP_ADDRESS_FLG, 0x00),
+            (DSP_ADDRESS_KON, 0xFF),
+        ]);
+        for (address, value) in writes {
+            batch.write_register(&batch_ram, address, value).unwrap();
+            stepped.write_register(&stepped_ram, address, value).unwrap();
         }
-        self.global_counter -= 1;
 
-        Some([out[0] as i16, out[1] as i16])
+        let mut nonzero = false;
+        for n in 0..16000u32 {
+            // 書き込みを伴わない同期をサンプル内の様々なステップで行う
+            stepped.synchronize(&mut stepped_ram, (n % DSP_STEPS_PER_SAMPLE as u32) as u8);
+            let expected = batch.tick(&mut batch_ram);
+            let actual = stepped.tick(&mut stepped_ram);
+            assert_eq!(expected, actual, "sample {}", n);
+            nonzero |= expected != Some([0, 0]);
+        }
+        assert!(nonzero);
+        assert_eq!(batch_ram[..], stepped_ram[..]);
     }
 }
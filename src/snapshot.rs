@@ -0,0 +1,184 @@
+/// スナップショットの識別子
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"SPCS";
+/// スナップショットのフォーマットバージョン
+pub const SNAPSHOT_VERSION: u16 = 1;
+/// スナップショットの最大サイズ（保存用バッファはこのサイズを確保すれば十分）
+pub const MAX_SNAPSHOT_SIZE: usize = 0x14000;
+
+/// スナップショットのエラー
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// バッファが不足している
+    BufferTooSmall,
+    /// 識別子が一致しない
+    InvalidMagic,
+    /// 対応していないバージョン
+    UnsupportedVersion { version: u16 },
+    /// 不正なデータ
+    InvalidData,
+}
+
+/// スナップショットの書き込み
+pub struct SnapshotWriter<'a> {
+    /// 出力先バッファ
+    buffer: &'a mut [u8],
+    /// 書き込み位置
+    pos: usize,
+}
+
+/// スナップショットの読み込み
+pub struct SnapshotReader<'a> {
+    /// 入力バッファ
+    data: &'a [u8],
+    /// 読み込み位置
+    pos: usize,
+}
+
+/// スナップショットの保存・復元ができる状態
+pub trait Snapshot {
+    /// 状態を保存
+    fn save_state(&self, writer: &mut SnapshotWriter) -> Result<(), SnapshotError>;
+    /// 状態を復元
+    fn load_state(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError>;
+}
+
+impl<'a> SnapshotWriter<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Self { buffer, pos: 0 }
+    }
+
+    /// 書き込んだバイト数
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// バイト列の書き込み
+    pub fn write_bytes(&mut self, data: &[u8]) -> Result<(), SnapshotError> {
+        if self.pos + data.len() > self.buffer.len() {
+            return Err(SnapshotError::BufferTooSmall);
+        }
+        self.buffer[self.pos..(self.pos + data.len())].copy_from_slice(data);
+        self.pos += data.len();
+        Ok(())
+    }
+
+    pub fn write_u8(&mut self, value: u8) -> Result<(), SnapshotError> {
+        self.write_bytes(&[value])
+    }
+
+    pub fn write_i8(&mut self, value: i8) -> Result<(), SnapshotError> {
+        self.write_u8(value as u8)
+    }
+
+    pub fn write_bool(&mut self, value: bool) -> Result<(), SnapshotError> {
+        self.write_u8(value as u8)
+    }
+
+    pub fn write_u16(&mut self, value: u16) -> Result<(), SnapshotError> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    pub fn write_i16(&mut self, value: i16) -> Result<(), SnapshotError> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    pub fn write_u32(&mut self, value: u32) -> Result<(), SnapshotError> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    pub fn write_i32(&mut self, value: i32) -> Result<(), SnapshotError> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    pub fn write_u64(&mut self, value: u64) -> Result<(), SnapshotError> {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    /// usizeは32bitとして書き込む
+    pub fn write_usize(&mut self, value: usize) -> Result<(), SnapshotError> {
+        self.write_u32(value as u32)
+    }
+}
+
+impl<'a> SnapshotReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// 読み込んだバイト数
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// バイト列の読み込み
+    pub fn read_bytes(&mut self, out: &mut [u8]) -> Result<(), SnapshotError> {
+        if self.pos + out.len() > self.data.len() {
+            return Err(SnapshotError::InvalidData);
+        }
+        out.copy_from_slice(&self.data[self.pos..(self.pos + out.len())]);
+        self.pos += out.len();
+        Ok(())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SnapshotError> {
+        let mut buf = [0u8; 1];
+        self.read_bytes(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    pub fn read_i8(&mut self) -> Result<i8, SnapshotError> {
+        Ok(self.read_u8()? as i8)
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SnapshotError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::InvalidData),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SnapshotError> {
+        let mut buf = [0u8; 2];
+        self.read_bytes(&mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub fn read_i16(&mut self) -> Result<i16, SnapshotError> {
+        let mut buf = [0u8; 2];
+        self.read_bytes(&mut buf)?;
+        Ok(i16::from_le_bytes(buf))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, SnapshotError> {
+        let mut buf = [0u8; 4];
+        self.read_bytes(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, SnapshotError> {
+        let mut buf = [0u8; 4];
+        self.read_bytes(&mut buf)?;
+        Ok(i32::from_le_bytes(buf))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SnapshotError> {
+        let mut buf = [0u8; 8];
+        self.read_bytes(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    pub fn read_usize(&mut self) -> Result<usize, SnapshotError> {
+        Ok(self.read_u32()? as usize)
+    }
+
+    /// 識別子の検査
+    pub fn expect_tag(&mut self, tag: &[u8; 4]) -> Result<(), SnapshotError> {
+        let mut buf = [0u8; 4];
+        self.read_bytes(&mut buf)?;
+        if &buf != tag {
+            return Err(SnapshotError::InvalidMagic);
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
 use crate::assembler::*;
+use crate::snapshot::*;
+use crate::spc_file::*;
 use crate::types::*;
 use log::trace;
 
@@ -8,6 +10,8 @@ const PSW_FLAG_N: u8 = 1 << 7;
 const PSW_FLAG_V: u8 = 1 << 6;
 /// ダイレクトページフラグ
 const PSW_FLAG_P: u8 = 1 << 5;
+/// ブレイクフラグ
+const PSW_FLAG_B: u8 = 1 << 4;
 /// ハーフキャリーフラグ
 const PSW_FLAG_H: u8 = 1 << 3;
 /// 割り込み可能フラグ（セット/リセットは可能だが効果なし）
@@ -18,6 +22,34 @@ const PSW_FLAG_Z: u8 = 1 << 1;
 const PSW_FLAG_C: u8 = 1 << 0;
 /// スタックのベースアドレス
 const STACK_BASE: usize = 0x100;
+/// BRK命令の割り込みベクタアドレス
+const BRK_VECTOR_ADDRESS: usize = 0xFFDE;
+/// 停止中に1ステップで経過させるサイクル数
+const HALTED_STEP_CYCLE: u8 = 2;
+/// 64kHz周期のクロックサイクル SPCのクロック(1.024MHz)を64KHzで割って得られる = 1024000 / 64000
+pub const CLOCK_TICK_CYCLE_64KHZ: u32 = 16;
+/// 命令ごとの最小サイクル数（分岐しない場合）
+/// 命令中のメモリアクセスのサイクル位置を求めるのに使用する
+const OPCODE_CYCLES: [u8; 256] = [
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 5, 4, 5, 4, 6, 8, // 0
+    2, 8, 4, 7, 4, 5, 5, 6, 5, 5, 6, 5, 2, 2, 4, 6, // 1
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 5, 4, 5, 4, 5, 4, // 2
+    2, 8, 4, 7, 4, 5, 5, 6, 5, 5, 6, 5, 2, 2, 3, 8, // 3
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 4, 4, 5, 4, 6, 6, // 4
+    2, 8, 4, 7, 4, 5, 5, 6, 5, 5, 4, 5, 2, 2, 4, 3, // 5
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 4, 4, 5, 4, 7, 5, // 6
+    2, 8, 4, 7, 4, 5, 5, 6, 5, 5, 5, 5, 2, 2, 3, 6, // 7
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 5, 4, 5, 2, 4, 5, // 8
+    2, 8, 4, 7, 4, 5, 5, 6, 5, 5, 5, 5, 2, 2, 12, 5, // 9
+    3, 8, 4, 5, 3, 4, 3, 6, 2, 6, 4, 4, 5, 2, 4, 4, // A
+    2, 8, 4, 7, 4, 5, 5, 6, 5, 5, 5, 5, 2, 2, 3, 4, // B
+    3, 8, 4, 5, 4, 5, 4, 7, 2, 5, 6, 4, 5, 2, 4, 9, // C
+    2, 8, 4, 7, 5, 6, 6, 7, 4, 5, 5, 5, 2, 2, 6, 3, // D
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 4, 5, 3, 4, 3, 4, 3, // E
+    2, 8, 4, 7, 4, 5, 5, 6, 3, 4, 5, 4, 2, 2, 4, 3, // F
+];
+/// IPL転送プロトコルで応答を待つ最大サイクル数（1秒）
+const IPL_HANDSHAKE_TIMEOUT_CYCLE: u32 = 1024000;
 /// テストレジスタアドレス
 pub const SPC_ADDRESS_TEST: usize = 0x00F0;
 /// コントロールレジスタアドレス
@@ -37,6 +69,99 @@ pub const SPC_ADDRESS_T0TARGET: usize = 0x00FA;
 pub const SPC_ADDRESS_T0OUT: usize = 0x00FD;
 pub const SPC_ADDRESS_T1OUT: usize = 0x00FE;
 pub const SPC_ADDRESS_T2OUT: usize = 0x00FF;
+/// IPL ROMがマップされる開始アドレス
+pub const SPC_ADDRESS_IPL_ROM: usize = 0xFFC0;
+
+/// IPL ROMのイメージ
+pub const IPL_ROM: [u8; 64] = [
+    0xCD, 0xEF, 0xBD, 0xE8, 0x00, 0xC6, 0x1D, 0xD0, 0xFC, 0x8F, 0xAA, 0xF4, 0x8F, 0xBB, 0xF5, 0x78,
+    0xCC, 0xF4, 0xD0, 0xFB, 0x2F, 0x19, 0xEB, 0xF4, 0xD0, 0xFC, 0x7E, 0xF4, 0xD0, 0x0B, 0xE4, 0xF5,
+    0xCB, 0xF4, 0xD7, 0x00, 0xFC, 0xD0, 0xF3, 0xAB, 0x01, 0x10, 0xEF, 0x7E, 0xF4, 0x10, 0xEB, 0xBA,
+    0xF6, 0xDA, 0x00, 0xBA, 0xF4, 0xC4, 0xF4, 0xDD, 0x5D, 0xD0, 0xDB, 0x1F, 0x00, 0x00, 0xC0, 0xFF,
+];
+
+/// ポートイベントキューに保持できる最大のイベント数
+pub const MAX_NUM_PORT_EVENTS: usize = 256;
+
+/// CPUポートイベント
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SPCPortEvent {
+    /// イベント発生時刻（SPCサイクル数）
+    pub cycle: u64,
+    /// ポート番号(0-3)
+    pub port: u8,
+    /// 値
+    pub value: u8,
+}
+
+/// ポートイベントのキュー（リングバッファ）
+struct PortEventQueue {
+    /// イベントバッファ
+    events: [SPCPortEvent; MAX_NUM_PORT_EVENTS],
+    /// 先頭位置
+    head: usize,
+    /// イベント数
+    length: usize,
+}
+
+impl PortEventQueue {
+    fn new() -> Self {
+        Self {
+            events: [SPCPortEvent {
+                cycle: 0,
+                port: 0,
+                value: 0,
+            }; MAX_NUM_PORT_EVENTS],
+            head: 0,
+            length: 0,
+        }
+    }
+
+    /// 末尾に追加（満杯ならばfalse）
+    fn push(&mut self, event: SPCPortEvent) -> bool {
+        if self.length >= MAX_NUM_PORT_EVENTS {
+            return false;
+        }
+        self.events[(self.head + self.length) % MAX_NUM_PORT_EVENTS] = event;
+        self.length += 1;
+        true
+    }
+
+    /// 末尾を参照
+    fn last(&self) -> Option<&SPCPortEvent> {
+        if self.length == 0 {
+            None
+        } else {
+            Some(&self.events[(self.head + self.length - 1) % MAX_NUM_PORT_EVENTS])
+        }
+    }
+
+    /// 先頭を参照
+    fn peek(&self) -> Option<&SPCPortEvent> {
+        if self.length == 0 {
+            None
+        } else {
+            Some(&self.events[self.head])
+        }
+    }
+
+    /// 先頭を取り出し
+    fn pop(&mut self) -> Option<SPCPortEvent> {
+        if self.length == 0 {
+            return None;
+        }
+        let event = self.events[self.head];
+        self.head = (self.head + 1) % MAX_NUM_PORT_EVENTS;
+        self.length -= 1;
+        Some(event)
+    }
+
+    /// 全イベントを破棄
+    fn clear(&mut self) {
+        self.head = 0;
+        self.length = 0;
+    }
+}
 
 /// SPCエミュレータ
 pub struct SPC<T>
@@ -49,10 +174,20 @@ where
     pub dsp: T,
     /// RAM(ARAM)
     ram: [u8; 65536],
-    /// CPU入力ポート（未使用！）
+    /// CPU入力ポート（ホスト -> SPC）
     cpu_port_in: [u8; 4],
-    /// CPU出力ポート（未使用！）
+    /// CPU出力ポート（SPC -> ホスト）
     cpu_port_out: [u8; 4],
+    /// 入力ポートへの書き込み予約キュー
+    port_in_queue: PortEventQueue,
+    /// 出力ポートへの書き込み記録キュー
+    port_out_queue: PortEventQueue,
+    /// 出力ポートへの書き込みを記録するか
+    port_out_recording: bool,
+    /// 実行したサイクル数
+    cycle_count: u64,
+    /// メモリの読み書きを行ったとみなすサイクル
+    access_cycle: u64,
     /// タイマーティックカウント
     tick_count: u64,
     /// タイマー有効フラグ
@@ -61,6 +196,12 @@ where
     timer_internal_count: [u8; 3],
     /// IPL(Initial program loader)有効か
     ipl_rom: bool,
+    /// SLEEP/STOPにより停止しているか
+    halted: bool,
+    /// 厳格モードか（無効時は実機と同様にエラーとなる操作を無視する）
+    strict_mode: bool,
+    /// 命令実行中に発生したエラー
+    pending_error: Option<SPCError>,
 }
 
 /// メモリビットのアドレスとビット位置を取得
@@ -82,10 +223,18 @@ where
             ram: [0; 65536],
             cpu_port_in: [0; 4],
             cpu_port_out: [0; 4],
+            port_in_queue: PortEventQueue::new(),
+            port_out_queue: PortEventQueue::new(),
+            port_out_recording: false,
+            cycle_count: 0,
+            access_cycle: 0,
             tick_count: 0,
             timer_enable: [false; 3],
             timer_internal_count: [0; 3],
             ipl_rom: false,
+            halted: false,
+            strict_mode: false,
+            pending_error: None,
         };
         emu.ram.copy_from_slice(ram);
 
@@ -100,21 +249,339 @@ where
         // DSPレジスタのセットアップ
         emu.dsp.initialize(&mut emu.ram, dsp_register);
 
+        // セットアップ中のエラーは無視
+        emu.pending_error = None;
+
         emu
     }
 
+    /// 電源投入直後の状態で生成（IPL ROMから実行を開始する）
+    pub fn new_power_on() -> SPC<T> {
+        let reset_vector = make_u16_from_u8(&IPL_ROM[0x3E..0x40]);
+        let reg = SPCRegister {
+            a: 0,
+            x: 0,
+            y: 0,
+            sp: 0,
+            pc: reset_vector,
+            psw: 0,
+        };
+        // IPL ROM有効・入力ポートクリア・タイマー停止
+        let mut ram = [0u8; 65536];
+        ram[SPC_ADDRESS_CONTROL] = 0xB0;
+        // DSPはリセット状態（ソフトリセット・ミュート・エコー書き込み無効）
+        let mut dsp_register = [0u8; 128];
+        dsp_register[DSP_ADDRESS_FLG as usize] = 0xE0;
+        Self::new(&reg, &ram, &dsp_register)
+    }
+
+    /// IPL ROMの転送プロトコルでデータブロックを転送し、エントリポイントにジャンプさせる
+    /// blocksは(転送先アドレス, データ)の組。IPL ROM実行中の状態から呼び出すこと
+    pub fn upload_via_ipl(&mut self, blocks: &[(u16, &[u8])], entry: u16) -> Result<(), SPCError> {
+        let mut clock_cycle = 0;
+
+        // IPLの準備完了($BBAA)を待つ
+        self.run_until_cpu_port_out(0, 0xAA, &mut clock_cycle)?;
+        self.run_until_cpu_port_out(1, 0xBB, &mut clock_cycle)?;
+
+        // 最初の開始合図は$CC
+        let mut kick = 0xCC;
+        for (address, data) in blocks {
+            // 空のブロックはプロトコル上転送できないため飛ばす
+            if data.is_empty() {
+                continue;
+            }
+            // 転送先アドレスを設定して開始合図
+            self.write_cpu_port(2, (*address & 0xFF) as u8);
+            self.write_cpu_port(3, (*address >> 8) as u8);
+            self.write_cpu_port(1, 1);
+            self.write_cpu_port(0, kick);
+            self.run_until_cpu_port_out(0, kick, &mut clock_cycle)?;
+
+            // 1バイトずつ転送（ポート0にはインデックスの下位8bitを書き、エコーを待つ）
+            let mut index = 0u8;
+            for byte in data.iter() {
+                self.write_cpu_port(1, *byte);
+                self.write_cpu_port(0, index);
+                self.run_until_cpu_port_out(0, index, &mut clock_cycle)?;
+                index = index.wrapping_add(1);
+            }
+
+            // 次の合図は最後のインデックス+2以上かつ0以外
+            kick = index.wrapping_add(1);
+            if kick == 0 {
+                kick = 1;
+            }
+        }
+
+        // エントリポイントを設定して実行開始
+        self.write_cpu_port(2, (entry & 0xFF) as u8);
+        self.write_cpu_port(3, (entry >> 8) as u8);
+        self.write_cpu_port(1, 0);
+        self.write_cpu_port(0, kick);
+        self.run_until_cpu_port_out(0, kick, &mut clock_cycle)?;
+
+        // ジャンプ命令を実行させる
+        let mut elapsed = 0;
+        while self.reg.pc != entry {
+            if elapsed >= IPL_HANDSHAKE_TIMEOUT_CYCLE {
+                return Err(SPCError::IPLHandshakeTimeout {
+                    port: 0,
+                    expected: kick,
+                });
+            }
+            elapsed += self.run_cycle(&mut clock_cycle)? as u32;
+        }
+
+        Ok(())
+    }
+
+    /// 1命令実行し、経過サイクルに応じてタイマー・DSPを進める
+    fn run_cycle(&mut self, clock_cycle: &mut u32) -> Result<u8, SPCError> {
+        let cycle = self.execute_step()?;
+        *clock_cycle += cycle as u32;
+        while *clock_cycle >= CLOCK_TICK_CYCLE_64KHZ {
+            *clock_cycle -= CLOCK_TICK_CYCLE_64KHZ;
+            self.clock_tick_64k_hz();
+        }
+        Ok(cycle)
+    }
+
+    /// CPU出力ポートが指定値になるまで実行
+    fn run_until_cpu_port_out(
+        &mut self,
+        port: usize,
+        value: u8,
+        clock_cycle: &mut u32,
+    ) -> Result<(), SPCError> {
+        let mut elapsed = 0;
+        while self.cpu_port_out[port] != value {
+            if elapsed >= IPL_HANDSHAKE_TIMEOUT_CYCLE {
+                return Err(SPCError::IPLHandshakeTimeout {
+                    port: port as u8,
+                    expected: value,
+                });
+            }
+            elapsed += self.run_cycle(clock_cycle)? as u32;
+        }
+        Ok(())
+    }
+
+    /// 現在の状態をSPCファイルとして取り出す
+    /// ヘッダはCPUレジスタのみ現在の値で上書きし、その他のタグ情報はそのまま使用する
+    pub fn export_spc_file(&self, header: &SPCFileHeader) -> SPCFile {
+        let mut spc_file = SPCFile {
+            header: header.clone(),
+            extended_tag: None,
+            ram: self.ram,
+            dsp_register: [0; 128],
+            xram_buffer: [0; 64],
+        };
+        spc_file.header.spc_register = self.reg.clone();
+
+        // ポートの領域にはSPCから見える入力値を置く（読み込み時に入力ポートとして復元される）
+        spc_file.ram[SPC_ADDRESS_CPUIO0..(SPC_ADDRESS_CPUIO3 + 1)]
+            .copy_from_slice(&self.cpu_port_in);
+
+        // DSPレジスタを読み出し（厳格モードで読めないアドレスは0とする）
+        for address in 0..128 {
+            spc_file.dsp_register[address] =
+                self.dsp.read_register(&self.ram, address as u8).unwrap_or(0);
+        }
+
+        // IPL ROMに隠れたRAMの内容
+        spc_file
+            .xram_buffer
+            .copy_from_slice(&self.ram[SPC_ADDRESS_IPL_ROM..]);
+
+        spc_file
+    }
+
+    /// スナップショットの保存。書き込んだバイト数を返す
+    /// 入力ポートの書き込み予約・出力ポートの記録キューは含まない
+    pub fn save_snapshot(&self, buffer: &mut [u8]) -> Result<usize, SnapshotError> {
+        let mut writer = SnapshotWriter::new(buffer);
+        writer.write_bytes(&SNAPSHOT_MAGIC)?;
+        writer.write_u16(SNAPSHOT_VERSION)?;
+        // CPU
+        writer.write_u8(self.reg.a)?;
+        writer.write_u8(self.reg.x)?;
+        writer.write_u8(self.reg.y)?;
+        writer.write_u8(self.reg.sp)?;
+        writer.write_u16(self.reg.pc)?;
+        writer.write_u8(self.reg.psw)?;
+        // RAM
+        writer.write_bytes(&self.ram)?;
+        // ポート
+        writer.write_bytes(&self.cpu_port_in)?;
+        writer.write_bytes(&self.cpu_port_out)?;
+        // タイマー
+        writer.write_u64(self.cycle_count)?;
+        writer.write_u64(self.tick_count)?;
+        for enable in self.timer_enable.iter() {
+            writer.write_bool(*enable)?;
+        }
+        writer.write_bytes(&self.timer_internal_count)?;
+        writer.write_bool(self.ipl_rom)?;
+        writer.write_bool(self.halted)?;
+        // DSP
+        self.dsp.save_state(&mut writer)?;
+        Ok(writer.position())
+    }
+
+    /// スナップショットからの復元
+    /// 失敗した場合の状態は不定のため、再度復元するか作り直すこと
+    pub fn load_snapshot(&mut self, data: &[u8]) -> Result<(), SnapshotError> {
+        let mut reader = SnapshotReader::new(data);
+        reader.expect_tag(&SNAPSHOT_MAGIC)?;
+        let version = reader.read_u16()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion { version });
+        }
+        // CPU
+        self.reg.a = reader.read_u8()?;
+        self.reg.x = reader.read_u8()?;
+        self.reg.y = reader.read_u8()?;
+        self.reg.sp = reader.read_u8()?;
+        self.reg.pc = reader.read_u16()?;
+        self.reg.psw = reader.read_u8()?;
+        // RAM
+        reader.read_bytes(&mut self.ram)?;
+        // ポート
+        reader.read_bytes(&mut self.cpu_port_in)?;
+        reader.read_bytes(&mut self.cpu_port_out)?;
+        // タイマー
+        self.cycle_count = reader.read_u64()?;
+        self.access_cycle = self.cycle_count;
+        self.tick_count = reader.read_u64()?;
+        for enable in self.timer_enable.iter_mut() {
+            *enable = reader.read_bool()?;
+        }
+        reader.read_bytes(&mut self.timer_internal_count)?;
+        self.ipl_rom = reader.read_bool()?;
+        self.halted = reader.read_bool()?;
+        // DSP
+        self.dsp.load_state(&mut reader)?;
+        // キューの内容は時刻が合わなくなるため破棄
+        self.port_in_queue.clear();
+        self.port_out_queue.clear();
+        self.pending_error = None;
+        Ok(())
+    }
+
+    /// 厳格モードの設定（既定は無効）
+    /// 有効にすると、TEST・TxOUTへの書き込みや未使用DSPレジスタの読み込みをエラーとして報告する
+    /// 無効時は実機と同様に扱う
+    pub fn set_strict_mode(&mut self, strict: bool) {
+        self.strict_mode = strict;
+        self.dsp.set_strict_mode(strict);
+    }
+
     /// ステップ実行
-    pub fn execute_step(&mut self) -> u8 {
-        let (opcode, len) = parse_opcode(&self.ram[(self.reg.pc as usize)..]);
+    pub fn execute_step(&mut self) -> Result<u8, SPCError> {
+        // 時刻に達した入力ポートへの書き込みを反映
+        while let Some(event) = self.port_in_queue.peek() {
+            if event.cycle > self.cycle_count {
+                break;
+            }
+            self.cpu_port_in[event.port as usize] = event.value;
+            self.port_in_queue.pop();
+        }
+        // 停止中は命令を実行せずサイクルのみ経過させる（タイマー・DSPは動作を続ける）
+        if self.halted {
+            self.cycle_count += HALTED_STEP_CYCLE as u64;
+            self.access_cycle = self.cycle_count;
+            return Ok(HALTED_STEP_CYCLE);
+        }
+        // 命令がアドレスの末尾をまたぐ場合はRAMの先頭に回り込ませる
+        let pc = self.reg.pc as usize;
+        let mut fetch = [0u8; 3];
+        for (i, byte) in fetch.iter_mut().enumerate() {
+            *byte = self.peek_ram_u8((pc + i) & 0xFFFF);
+        }
+        let (opcode, len) = parse_opcode(&fetch);
         trace!(
             "{:#06X}: {:02X?} {:X?} {:X?}",
             self.reg.pc,
-            &self.ram[(self.reg.pc as usize)..((self.reg.pc + len) as usize)],
+            &fetch[..(len as usize)],
             opcode,
             self.reg
         );
-        self.reg.pc += len;
-        self.execute_opcode(&opcode)
+        self.reg.pc = self.reg.pc.wrapping_add(len);
+        // 命令中の読み書きは命令の最後のサイクルで行われたものとみなす
+        self.access_cycle = self.cycle_count + OPCODE_CYCLES[fetch[0] as usize] as u64 - 1;
+        let cycle = self.execute_opcode(&opcode);
+        self.cycle_count += cycle as u64;
+        // 命令外からの読み書きは現在のサイクルで行う
+        self.access_cycle = self.cycle_count;
+        match self.pending_error.take() {
+            Some(err) => Err(err),
+            None => Ok(cycle),
+        }
+    }
+
+    /// SLEEP/STOPにより停止しているか
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// これまでに実行したサイクル数
+    pub fn get_cycle_count(&self) -> u64 {
+        self.cycle_count
+    }
+
+    /// CPU入力ポートへの書き込み（SNES本体側からの書き込みに相当）
+    pub fn write_cpu_port(&mut self, port: usize, value: u8) {
+        self.cpu_port_in[port & 0x3] = value;
+    }
+
+    /// CPU出力ポートの読み込み（SNES本体側からの読み込みに相当）
+    pub fn read_cpu_port(&self, port: usize) -> u8 {
+        self.cpu_port_out[port & 0x3]
+    }
+
+    /// 指定サイクルでのCPU入力ポートへの書き込みを予約
+    /// 予約は時刻順に行うこと。直前の予約より前の時刻やキューが満杯の場合はエラーを返し、予約しない
+    pub fn schedule_cpu_port_write(
+        &mut self,
+        cycle: u64,
+        port: usize,
+        value: u8,
+    ) -> Result<(), SPCError> {
+        if let Some(last) = self.port_in_queue.last()
+            && cycle < last.cycle
+        {
+            return Err(SPCError::PortEventOutOfOrder {
+                cycle,
+                last_cycle: last.cycle,
+            });
+        }
+        if !self.port_in_queue.push(SPCPortEvent {
+            cycle,
+            port: (port & 0x3) as u8,
+            value,
+        }) {
+            return Err(SPCError::PortEventQueueFull);
+        }
+        Ok(())
+    }
+
+    /// 予約済みのCPU入力ポートへの書き込みを破棄
+    pub fn clear_scheduled_cpu_port_writes(&mut self) {
+        self.port_in_queue.clear();
+    }
+
+    /// CPU出力ポートへの書き込み記録の有効/無効を設定
+    pub fn set_cpu_port_output_recording(&mut self, enable: bool) {
+        self.port_out_recording = enable;
+        if !enable {
+            self.port_out_queue.clear();
+        }
+    }
+
+    /// 記録されたCPU出力ポートへの書き込みを古い順に取り出し
+    pub fn pop_cpu_port_output_event(&mut self) -> Option<SPCPortEvent> {
+        self.port_out_queue.pop()
     }
 
     /// クロックカウンタの更新
@@ -130,6 +597,8 @@ where
     }
 
     /// クロックティック
+    /// タイマーの更新とDSPの定期処理にはエラーとなる経路がないため、Resultではなく出力のみを返す
+    /// （エラーはレジスタの読み書きを行うexecute_stepから返る）
     pub fn clock_tick_64k_hz(&mut self) -> Option<T::Output> {
         self.tick_count = self.tick_count.wrapping_add(1);
         // 8kHzタイマー
@@ -153,6 +622,17 @@ where
         None
     }
 
+    /// 現在のサンプル内でのDSPのステップ位置
+    /// 64kHzのティックがCLOCK_TICK_CYCLE_64KHZサイクルごとに呼ばれている前提で、サンプルの開始からの経過サイクル数を求める
+    /// 命令の途中の読み書きは命令の最後のサイクルで行われたものとみなす
+    /// （サンプルの終わりを越える場合は、まだ処理していない次のサンプルではなく現在のサンプルの最後のステップとする）
+    fn dsp_step(&self) -> u8 {
+        let sample_start = (self.tick_count & !1).wrapping_mul(CLOCK_TICK_CYCLE_64KHZ as u64);
+        self.access_cycle
+            .saturating_sub(sample_start)
+            .min((DSP_STEPS_PER_SAMPLE - 1) as u64) as u8
+    }
+
     /// タイマーに関するレジスタの書き込み処理
     fn write_timer_register(&mut self, value: u8) {
         for id in 0..3 {
@@ -170,49 +650,69 @@ where
         }
     }
 
-    /// CPUIOリードポートのクリア
+    /// CPUIOリードポートのクリア（出力ポートには影響しない）
     fn clear_cpuio_read_ports(&mut self, value: u8) {
         if (value & 0x10) != 0 {
-            self.write_ram_u8(SPC_ADDRESS_CPUIO0, 0);
-            self.write_ram_u8(SPC_ADDRESS_CPUIO1, 0);
             self.cpu_port_in[0] = 0;
             self.cpu_port_in[1] = 0;
         }
         if (value & 0x20) != 0 {
-            self.write_ram_u8(SPC_ADDRESS_CPUIO2, 0);
-            self.write_ram_u8(SPC_ADDRESS_CPUIO3, 0);
             self.cpu_port_in[2] = 0;
             self.cpu_port_in[3] = 0;
         }
     }
 
+    /// 命令実行中のエラーを記録（厳格モード時のみ。最初のエラーを保持）
+    fn record_error(&mut self, err: SPCError) {
+        if self.strict_mode && self.pending_error.is_none() {
+            self.pending_error = Some(err);
+        }
+    }
+
     /// RAMへの書き込み
     pub fn write_ram_u8(&mut self, address: usize, value: u8) {
         // CPUレジスタへの書き込み
         if (address >= SPC_ADDRESS_TEST) && (address <= SPC_ADDRESS_T2OUT) {
             match address {
                 SPC_ADDRESS_TEST => {
-                    panic!("CANNOT write to test register!!");
+                    // 実機の動作を変えてしまうため書き込みは無視する
+                    self.record_error(SPCError::WriteToTestRegister { value });
+                    return;
                 }
                 SPC_ADDRESS_CONTROL => {
                     self.write_timer_register(value);
                     self.clear_cpuio_read_ports(value);
-                    if (value & 0x80) != 0 {
-                        self.ipl_rom = true;
-                    }
+                    self.ipl_rom = (value & 0x80) != 0;
                 }
                 SPC_ADDRESS_DSPADDR => {
                     // 何もしないがアドレスをラッチすべき？
                 }
                 SPC_ADDRESS_DSPDATA => {
-                    self.dsp
-                        .write_register(&self.ram, self.ram[SPC_ADDRESS_DSPADDR], value);
+                    let step = self.dsp_step();
+                    self.dsp.synchronize(&mut self.ram, step);
+                    if let Err(err) =
+                        self.dsp
+                            .write_register(&self.ram, self.ram[SPC_ADDRESS_DSPADDR], value)
+                    {
+                        self.record_error(err);
+                    }
                 }
                 SPC_ADDRESS_CPUIO0 | SPC_ADDRESS_CPUIO1 | SPC_ADDRESS_CPUIO2 | SPC_ADDRESS_CPUIO3 => {
-                    self.cpu_port_out[address - SPC_ADDRESS_CPUIO0] = value;
+                    let port = address - SPC_ADDRESS_CPUIO0;
+                    self.cpu_port_out[port] = value;
+                    if self.port_out_recording {
+                        // 満杯の場合は記録しない
+                        self.port_out_queue.push(SPCPortEvent {
+                            cycle: self.cycle_count,
+                            port: port as u8,
+                            value,
+                        });
+                    }
                 }
                 SPC_ADDRESS_T0OUT | SPC_ADDRESS_T1OUT | SPC_ADDRESS_T2OUT => {
-                    panic!("CANNOT write to TxOUT register!!");
+                    // 読み取り専用のため書き込みは無視される
+                    self.record_error(SPCError::WriteToTimerOutputRegister { address, value });
+                    return;
                 }
                 _ => {}
             }
@@ -221,9 +721,18 @@ where
         trace!("W: 0x{:04X} <- {:02X}", address, value);
     }
 
+    /// IPL ROMのマッピングを考慮したメモリの参照（副作用なし）
+    fn peek_ram_u8(&self, address: usize) -> u8 {
+        if self.ipl_rom && address >= SPC_ADDRESS_IPL_ROM {
+            IPL_ROM[address - SPC_ADDRESS_IPL_ROM]
+        } else {
+            self.ram[address]
+        }
+    }
+
     /// RAMからの読み込み
     pub fn read_ram_u8(&mut self, address: usize) -> u8 {
-        trace!("R: 0x{:04X} -> {:02X}", address, self.ram[address]);
+        trace!("R: 0x{:04X} -> {:02X}", address, self.peek_ram_u8(address));
         // CPUレジスタからの読み込み
         if (address >= SPC_ADDRESS_TEST) && (address <= SPC_ADDRESS_T2OUT) {
             match address {
@@ -237,7 +746,15 @@ where
                     // 何もしないがアドレスをラッチすべき？
                 }
                 SPC_ADDRESS_DSPDATA => {
-                    return self.dsp.read_register(&self.ram, self.ram[SPC_ADDRESS_DSPADDR]);
+                    let step = self.dsp_step();
+                    self.dsp.synchronize(&mut self.ram, step);
+                    return match self.dsp.read_register(&self.ram, self.ram[SPC_ADDRESS_DSPADDR]) {
+                        Ok(value) => value,
+                        Err(err) => {
+                            self.record_error(err);
+                            0
+                        }
+                    };
                 }
                 SPC_ADDRESS_CPUIO0 | SPC_ADDRESS_CPUIO1 | SPC_ADDRESS_CPUIO2 | SPC_ADDRESS_CPUIO3 => {
                     return self.cpu_port_in[address - SPC_ADDRESS_CPUIO0];
@@ -251,17 +768,15 @@ where
                 _ => {}
             }
         }
-        self.ram[address]
+        self.peek_ram_u8(address)
     }
 
     /// RAMからの読み込み
     fn read_ram_u16(&self, address: usize) -> u16 {
-        trace!(
-            "R16: 0x{:04X} -> {:04X}",
-            address,
-            ((self.ram[address + 1] as u16) << 8) | self.ram[address] as u16
-        );
-        ((self.ram[address + 1] as u16) << 8) | self.ram[address] as u16
+        let value = ((self.peek_ram_u8((address + 1) & 0xFFFF) as u16) << 8)
+            | self.peek_ram_u8(address & 0xFFFF) as u16;
+        trace!("R16: 0x{:04X} -> {:04X}", address, value);
+        value
     }
 
     /// ダイレクトページのアドレスを取得
@@ -918,17 +1433,31 @@ where
                 3
             }
             SPCOpcode::BRK => {
-                panic!("This emulator does not support BRK instruction!");
+                let jmp_pc = self.read_ram_u16(BRK_VECTOR_ADDRESS);
+                self.push_stack(((self.reg.pc >> 8) & 0xFF) as u8);
+                self.push_stack(((self.reg.pc >> 0) & 0xFF) as u8);
+                self.push_stack(self.reg.psw);
+                self.set_psw_flag(PSW_FLAG_B, true);
+                self.set_psw_flag(PSW_FLAG_I, false);
+                self.reg.pc = jmp_pc;
+                8
             }
             SPCOpcode::RETI => {
-                panic!("This emulator does not support RETI instruction!");
+                self.reg.psw = self.pop_stack();
+                let low = self.pop_stack() as u16;
+                let high = self.pop_stack() as u16;
+                self.reg.pc = (high << 8) | low;
+                6
             }
             // その他の命令
             SPCOpcode::SLEEP => {
-                panic!("This emulator does not support SLEEP instruction!");
+                // 割り込みは発生しないため、実質的に停止する
+                self.halted = true;
+                3
             }
             SPCOpcode::STOP => {
-                panic!("This emulator does not support STOP instruction!");
+                self.halted = true;
+                3
             }
         }
     }
@@ -1821,3 +2350,166 @@ where
         cycle
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdsp::*;
+
+    /// ベンチマーク用のSPCファイルから、ボイス1以外を固定ゲインで発音させたエミュレータを作成
+    fn stepped_spc() -> SPC<SDSP> {
+        let data = std::fs::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/benches/data/Eyes on Me! [2 = Original] [1 = OG Echo].spc"
+        ))
+        .unwrap();
+        let spc_file = parse_spc_file(&data).unwrap();
+        let mut spc: SPC<SDSP> = SPC::new(
+            &spc_file.header.spc_register,
+            &spc_file.ram,
+            &spc_file.dsp_register,
+        );
+        spc.dsp.set_cycle_stepped(true);
+        for ch in 0..8u8 {
+            let base = ch << 4;
+            for (address, value) in [
+                (base, 0x30),
+                (base + 1, 0x30),
+                (base + 2, 0x00),
+                (base + 3, 0x08 + ch),
+                (base + 4, ch),
+                (base + 5, 0x00),
+                (base + 7, 0x7F),
+            ] {
+                write_dsp(&mut spc, 0, address, value);
+            }
+        }
+        write_dsp(&mut spc, 0, DSP_ADDRESS_FLG, 0x00);
+        write_dsp(&mut spc, 0, DSP_ADDRESS_KON, 0xFD);
+        // キーオンの待機を終えて発音している状態まで進める
+        for _ in 0..64 {
+            finish_sample(&mut spc);
+        }
+        spc
+    }
+
+    /// 現在のサンプルの指定ステップに合わせる
+    fn seek_step(spc: &mut SPC<SDSP>, step: u8) {
+        assert_eq!(spc.tick_count % 2, 0);
+        spc.cycle_count = spc.tick_count * CLOCK_TICK_CYCLE_64KHZ as u64 + step as u64;
+        spc.access_cycle = spc.cycle_count;
+    }
+
+    /// 現在のサンプルの指定ステップでDSPレジスタに書き込む
+    fn write_dsp(spc: &mut SPC<SDSP>, step: u8, address: u8, value: u8) {
+        seek_step(spc, step);
+        spc.write_ram_u8(SPC_ADDRESS_DSPADDR, address);
+        spc.write_ram_u8(SPC_ADDRESS_DSPDATA, value);
+    }
+
+    /// 現在のサンプルの残りを処理して出力を得る
+    fn finish_sample(spc: &mut SPC<SDSP>) -> [i16; 2] {
+        assert_eq!(spc.clock_tick_64k_hz(), None);
+        spc.clock_tick_64k_hz().unwrap()
+    }
+
+    /// ボイス5の処理（ステップ15）より前の書き込みのみ現在のサンプルに反映される
+    #[test]
+    fn voice_volume_write_before_and_after_voice_slot() {
+        const V5VOLL: u8 = (5 << 4) | DSP_ADDRESS_V0VOLL;
+        let mut reference = stepped_spc();
+        let mut early = stepped_spc();
+        let mut late = stepped_spc();
+
+        write_dsp(&mut early, 14, V5VOLL, 0x00);
+        write_dsp(&mut late, 16, V5VOLL, 0x00);
+        finish_sample(&mut reference);
+        finish_sample(&mut early);
+        finish_sample(&mut late);
+        let reference_out = reference.dsp.stems().voice[5][0];
+        assert_ne!(reference_out, 0);
+        assert_eq!(early.dsp.stems().voice[5][0], 0);
+        assert_eq!(late.dsp.stems().voice[5][0], reference_out);
+
+        // 遅れた書き込みは次のサンプルから反映される
+        finish_sample(&mut late);
+        assert_eq!(late.dsp.stems().voice[5][0], 0);
+    }
+
+    /// ポーリング（ステップ0）より後に書き込んだKONは次のサンプルのポーリングで読み込まれる
+    #[test]
+    fn late_keyon_is_polled_in_next_sample() {
+        let mut late = stepped_spc();
+        let mut next = stepped_spc();
+
+        write_dsp(&mut late, 31, DSP_ADDRESS_KON, 0x02);
+        finish_sample(&mut late);
+        finish_sample(&mut next);
+        write_dsp(&mut next, 0, DSP_ADDRESS_KON, 0x02);
+
+        let mut sounded = false;
+        for _ in 0..256 {
+            assert_eq!(finish_sample(&mut late), finish_sample(&mut next));
+            assert_eq!(late.dsp.stems().voice[1], next.dsp.stems().voice[1]);
+            sounded |= late.dsp.stems().voice[1] != [0, 0];
+        }
+        assert!(sounded);
+    }
+
+    /// 命令中の書き込みは命令の最後のサイクルで行われる
+    #[test]
+    fn instruction_write_lands_on_last_cycle() {
+        const V5VOLL: u8 = (5 << 4) | DSP_ADDRESS_V0VOLL;
+        let mut direct = stepped_spc();
+        let mut instruction = stepped_spc();
+
+        // MOV $F3, A（4サイクル）をステップ13から実行すると書き込みはステップ16になり、ボイス5に間に合わない
+        write_dsp(&mut direct, 13, V5VOLL, 0x00);
+        seek_step(&mut instruction, 13);
+        instruction.write_ram_u8(SPC_ADDRESS_DSPADDR, V5VOLL);
+        instruction.ram[0x0300..0x0302].copy_from_slice(&[0xC4, 0xF3]);
+        instruction.reg.pc = 0x0300;
+        instruction.reg.a = 0x00;
+        assert_eq!(instruction.execute_step(), Ok(4));
+
+        finish_sample(&mut direct);
+        finish_sample(&mut instruction);
+        assert_eq!(direct.dsp.stems().voice[5][0], 0);
+        assert_ne!(instruction.dsp.stems().voice[5][0], 0);
+        finish_sample(&mut instruction);
+        assert_eq!(instruction.dsp.stems().voice[5][0], 0);
+    }
+
+    /// IPL ROMの転送プロトコルでドライバと256バイトを超えるブロックを転送する
+    #[test]
+    fn upload_via_ipl_runs_driver() {
+        // MOV A,#$5A; MOV $F4,A; BRA $
+        const DRIVER: [u8; 6] = [0xE8, 0x5A, 0xC4, 0xF4, 0x2F, 0xFE];
+        let block: std::vec::Vec<u8> = (0..600u32).map(|i| (i * 7) as u8).collect();
+        let mut spc: SPC<SDSP> = SPC::new_power_on();
+        spc.upload_via_ipl(&[(0x0200, &DRIVER), (0x1000, &block)], 0x0200)
+            .unwrap();
+        assert_eq!(spc.reg.pc, 0x0200);
+
+        for _ in 0..4 {
+            spc.execute_step().unwrap();
+        }
+        assert_eq!(spc.read_cpu_port(0), 0x5A);
+        assert_eq!(spc.ram[0x0200..0x0206], DRIVER);
+        assert_eq!(spc.ram[0x1000..0x1000 + block.len()], block[..]);
+    }
+
+    /// IPL ROMが無効だと応答が得られずタイムアウトする
+    #[test]
+    fn upload_via_ipl_times_out_without_ipl_rom() {
+        let mut spc: SPC<SDSP> = SPC::new_power_on();
+        spc.write_ram_u8(SPC_ADDRESS_CONTROL, 0x30);
+        assert_eq!(
+            spc.upload_via_ipl(&[(0x0200, &[0x00])], 0x0200),
+            Err(SPCError::IPLHandshakeTimeout {
+                port: 0,
+                expected: 0xAA
+            })
+        );
+    }
+}
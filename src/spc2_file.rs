@@ -0,0 +1,448 @@
+//! SPC2ファイル（複数曲をまとめたコンテナ）の読み書き
+//!
+//! レイアウト（数値はリトルエンディアン）
+//! - ヘッダ(16バイト): "KSPC", 0x1A, メジャーバージョン, マイナーバージョン, 曲数(u16), 予約(7バイト)
+//! - 曲ごとのブロック(1024バイト)
+//!   - 0x000: DSPレジスタ(128), 0x080: IPL ROM領域のRAM(64), 0x0C0: RAMページ番号(u16 x 256)
+//!   - 0x2C0: PC(u16), A, X, Y, PSW, SP
+//!   - 0x2C7: ダンプした日付(u32, yyyymmdd), 0x2CB: 演奏時間(u16, 秒), 0x2CD: フェードアウト時間(u32, ミリ秒)
+//!   - 0x2D1: 初期チャンネル無効, 0x2D2: エミュレータ, 0x2D3: ディスク番号, 0x2D4: トラック番号(u16), 0x2D6: 著作権年(u16)
+//!   - 0x300から32バイトずつ: 曲名, ゲーム名, アーティスト, ダンプした人, コメント, サウンドトラック名, 発売元
+//! - RAMページ(256バイト単位): 全曲で重複を除いて格納
+//!
+//! SPC2仕様のバージョン1.0（メジャーバージョン1・マイナーバージョン0）のレイアウトに従う
+//! 曲ブロックの0x3E0以降（元のファイル名などの領域）には対応せず、書き出し時は0で埋め、読み込み時は無視する
+use crate::spc_file::*;
+use crate::types::*;
+
+/// SPC2ファイルの識別子
+const SPC2_SIGNATURE: &[u8; 5] = b"KSPC\x1A";
+/// 対応するメジャーバージョン
+const SPC2_MAJOR_VERSION: u8 = 1;
+/// 書き出すマイナーバージョン
+const SPC2_MINOR_VERSION: u8 = 0;
+/// ヘッダサイズ
+const SPC2_HEADER_SIZE: usize = 16;
+/// 曲ごとのブロックサイズ
+const SPC2_SONG_BLOCK_SIZE: usize = 1024;
+/// RAMページのサイズ
+const SPC2_PAGE_SIZE: usize = 256;
+/// 1曲あたりのRAMページ数
+const SPC2_NUM_PAGES_PER_SONG: usize = 65536 / SPC2_PAGE_SIZE;
+/// 格納できる最大曲数
+pub const SPC2_MAX_NUM_SONGS: usize = 0xFFFF;
+/// 格納できる最大RAMページ数（ページ番号は16bit）
+const SPC2_MAX_NUM_PAGES: usize = 0x10000;
+/// 文字列フィールドの長さ
+const SPC2_TEXT_LENGTH: usize = 32;
+
+/// 曲ブロック内のオフセット
+const SONG_DSP_REGISTER: usize = 0x000;
+const SONG_IPL_RAM: usize = 0x080;
+const SONG_PAGE_INDEX: usize = 0x0C0;
+const SONG_REGISTER: usize = 0x2C0;
+const SONG_DUMP_DATE: usize = 0x2C7;
+const SONG_DURATION: usize = 0x2CB;
+const SONG_FADEOUT_TIME: usize = 0x2CD;
+const SONG_CHANNEL_INVALID: usize = 0x2D1;
+const SONG_EMULATOR: usize = 0x2D2;
+const SONG_OST_DISC: usize = 0x2D3;
+const SONG_OST_TRACK: usize = 0x2D4;
+const SONG_COPYRIGHT_YEAR: usize = 0x2D6;
+const SONG_MUSIC_TITLE: usize = 0x300;
+const SONG_GAME_TITLE: usize = 0x320;
+const SONG_ARTIST: usize = 0x340;
+const SONG_DUMPER: usize = 0x360;
+const SONG_COMMENT: usize = 0x380;
+const SONG_OST_TITLE: usize = 0x3A0;
+const SONG_PUBLISHER: usize = 0x3C0;
+
+/// SPC2ファイル（読み込み元のデータを参照する）
+#[derive(Debug, Clone, Copy)]
+pub struct SPC2File<'a> {
+    /// ファイル全体のデータ
+    data: &'a [u8],
+    /// マイナーバージョン
+    pub minor_version: u8,
+    /// 曲数
+    num_songs: usize,
+}
+
+impl<'a> SPC2File<'a> {
+    /// 曲数
+    pub fn num_songs(&self) -> usize {
+        self.num_songs
+    }
+
+    /// 曲ブロックの参照
+    fn song_block(&self, index: usize) -> Option<&'a [u8]> {
+        if index >= self.num_songs {
+            return None;
+        }
+        let offset = SPC2_HEADER_SIZE + index * SPC2_SONG_BLOCK_SIZE;
+        Some(&self.data[offset..offset + SPC2_SONG_BLOCK_SIZE])
+    }
+
+    /// 曲のヘッダ情報を取得
+    pub fn song_header(&self, index: usize) -> Option<SPCFileHeader> {
+        let block = self.song_block(index)?;
+        let mut header = SPCFileHeader::new();
+        header.spc_register = SPCRegister {
+            pc: make_u16_from_u8(&block[SONG_REGISTER..SONG_REGISTER + 2]),
+            a: block[SONG_REGISTER + 2],
+            x: block[SONG_REGISTER + 3],
+            y: block[SONG_REGISTER + 4],
+            psw: block[SONG_REGISTER + 5],
+            sp: block[SONG_REGISTER + 6],
+        };
+        header.set_music_title(text_field(block, SONG_MUSIC_TITLE));
+        header.set_game_title(text_field(block, SONG_GAME_TITLE));
+        header.set_creator(text_field(block, SONG_DUMPER));
+        header.set_comment(text_field(block, SONG_COMMENT));
+        header.set_composer(text_field(block, SONG_ARTIST));
+        let dump_date = read_u32(block, SONG_DUMP_DATE);
+        header.generate_date = (dump_date % 100) as u8;
+        header.generate_month = ((dump_date / 100) % 100) as u8;
+        header.generate_year = ((dump_date / 10000) % 10000) as u16;
+        header.duration = make_u16_from_u8(&block[SONG_DURATION..SONG_DURATION + 2]);
+        header.fadeout_time = read_u32(block, SONG_FADEOUT_TIME);
+        header.initial_channel_invalid = block[SONG_CHANNEL_INVALID];
+        header.emurator_type =
+            emurator_type_from_u8(block[SONG_EMULATOR]).unwrap_or(EmuratorType::Unknown);
+        Some(header)
+    }
+
+    /// 曲の拡張タグを取得（ID666に含まれない情報が無ければNone）
+    pub fn song_extended_tag(&self, index: usize) -> Option<ExtendedID666> {
+        let block = self.song_block(index)?;
+        let mut tag = ExtendedID666::new();
+        let ost_title = text_field(block, SONG_OST_TITLE);
+        if !ost_title.is_empty() {
+            tag.ost_title = Some(XID6String::new(ost_title));
+        }
+        let publisher = text_field(block, SONG_PUBLISHER);
+        if !publisher.is_empty() {
+            tag.publisher = Some(XID6String::new(publisher));
+        }
+        if block[SONG_OST_DISC] != 0 {
+            tag.ost_disc = Some(block[SONG_OST_DISC]);
+        }
+        let ost_track = make_u16_from_u8(&block[SONG_OST_TRACK..SONG_OST_TRACK + 2]);
+        if ost_track != 0 {
+            tag.ost_track = Some(ost_track);
+        }
+        let copyright_year = make_u16_from_u8(&block[SONG_COPYRIGHT_YEAR..SONG_COPYRIGHT_YEAR + 2]);
+        if copyright_year != 0 {
+            tag.copyright_year = Some(copyright_year);
+        }
+
+        if tag.ost_title.is_none()
+            && tag.publisher.is_none()
+            && tag.ost_disc.is_none()
+            && tag.ost_track.is_none()
+            && tag.copyright_year.is_none()
+        {
+            return None;
+        }
+        Some(tag)
+    }
+
+    /// 曲をSPCファイルに展開
+    pub fn song(&self, index: usize) -> Option<SPCFile> {
+        let block = self.song_block(index)?;
+        let pages_offset = SPC2_HEADER_SIZE + self.num_songs * SPC2_SONG_BLOCK_SIZE;
+
+        let mut ram = [0u8; 65536];
+        for (i, page) in ram.chunks_exact_mut(SPC2_PAGE_SIZE).enumerate() {
+            let offset = SONG_PAGE_INDEX + 2 * i;
+            let page_index = make_u16_from_u8(&block[offset..offset + 2]) as usize;
+            let start = pages_offset + page_index * SPC2_PAGE_SIZE;
+            // ページ番号はパース時に検査済み
+            page.copy_from_slice(&self.data[start..start + SPC2_PAGE_SIZE]);
+        }
+
+        Some(SPCFile {
+            header: self.song_header(index)?,
+            extended_tag: self.song_extended_tag(index),
+            ram,
+            dsp_register: block[SONG_DSP_REGISTER..SONG_DSP_REGISTER + 128]
+                .try_into()
+                .unwrap(),
+            xram_buffer: block[SONG_IPL_RAM..SONG_IPL_RAM + 64].try_into().unwrap(),
+        })
+    }
+}
+
+/// 文字列フィールドの参照（ヌル文字以降は除く）
+fn text_field(block: &[u8], offset: usize) -> &[u8] {
+    let field = &block[offset..offset + SPC2_TEXT_LENGTH];
+    let length = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    &field[..length]
+}
+
+/// 文字列フィールドへの書き込み（長い場合は切り捨て）
+fn write_text_field(block: &mut [u8], offset: usize, text: &[u8]) {
+    let length = text.len().min(SPC2_TEXT_LENGTH);
+    block[offset..offset + SPC2_TEXT_LENGTH].fill(0);
+    block[offset..offset + length].copy_from_slice(&text[..length]);
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// SPC2ファイルのパース
+pub fn parse_spc2_file(data: &[u8]) -> Option<SPC2File<'_>> {
+    // ヘッダのチェック
+    if data.len() < SPC2_HEADER_SIZE
+        || &data[0..5] != SPC2_SIGNATURE
+        || data[5] != SPC2_MAJOR_VERSION
+    {
+        return None;
+    }
+    let num_songs = make_u16_from_u8(&data[7..9]) as usize;
+
+    // サイズチェック
+    let pages_offset = SPC2_HEADER_SIZE + num_songs * SPC2_SONG_BLOCK_SIZE;
+    if data.len() < pages_offset {
+        return None;
+    }
+    let num_pages = (data.len() - pages_offset) / SPC2_PAGE_SIZE;
+
+    // 全てのページ番号が範囲内か確認
+    for song in 0..num_songs {
+        let offset = SPC2_HEADER_SIZE + song * SPC2_SONG_BLOCK_SIZE + SONG_PAGE_INDEX;
+        let indices = &data[offset..offset + 2 * SPC2_NUM_PAGES_PER_SONG];
+        if indices
+            .chunks_exact(2)
+            .any(|index| make_u16_from_u8(index) as usize >= num_pages)
+        {
+            return None;
+        }
+    }
+
+    Some(SPC2File {
+        data,
+        minor_version: data[6],
+        num_songs,
+    })
+}
+
+/// SPC2ファイルとして書き出したときの最大サイズ（RAMページの重複が無い場合）
+pub fn spc2_file_max_size(num_songs: usize) -> usize {
+    SPC2_HEADER_SIZE + num_songs * (SPC2_SONG_BLOCK_SIZE + 65536)
+}
+
+/// 曲ブロックの書き出し（RAMページ番号以外）
+fn write_song_block(spc_file: &SPCFile, block: &mut [u8]) {
+    let header = &spc_file.header;
+    block.fill(0);
+    block[SONG_DSP_REGISTER..SONG_DSP_REGISTER + 128].copy_from_slice(&spc_file.dsp_register);
+    block[SONG_IPL_RAM..SONG_IPL_RAM + 64].copy_from_slice(&spc_file.xram_buffer);
+    block[SONG_REGISTER..SONG_REGISTER + 2].copy_from_slice(&header.spc_register.pc.to_le_bytes());
+    block[SONG_REGISTER + 2] = header.spc_register.a;
+    block[SONG_REGISTER + 3] = header.spc_register.x;
+    block[SONG_REGISTER + 4] = header.spc_register.y;
+    block[SONG_REGISTER + 5] = header.spc_register.psw;
+    block[SONG_REGISTER + 6] = header.spc_register.sp;
+    let dump_date = (header.generate_year as u32) * 10000
+        + (header.generate_month as u32) * 100
+        + header.generate_date as u32;
+    block[SONG_DUMP_DATE..SONG_DUMP_DATE + 4].copy_from_slice(&dump_date.to_le_bytes());
+    block[SONG_DURATION..SONG_DURATION + 2].copy_from_slice(&header.duration.to_le_bytes());
+    block[SONG_FADEOUT_TIME..SONG_FADEOUT_TIME + 4]
+        .copy_from_slice(&header.fadeout_time.to_le_bytes());
+    block[SONG_CHANNEL_INVALID] = header.initial_channel_invalid;
+    block[SONG_EMULATOR] = header.emurator_type.clone() as u8;
+    write_text_field(block, SONG_MUSIC_TITLE, text_field(&header.music_title, 0));
+    write_text_field(block, SONG_GAME_TITLE, text_field(&header.game_title, 0));
+    write_text_field(block, SONG_ARTIST, text_field(&header.composer, 0));
+    write_text_field(block, SONG_COMMENT, text_field(&header.comment, 0));
+    let creator_length = header
+        .creator
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(header.creator.len());
+    write_text_field(block, SONG_DUMPER, &header.creator[..creator_length]);
+
+    if let Some(tag) = &spc_file.extended_tag {
+        if let Some(ost_title) = &tag.ost_title {
+            write_text_field(block, SONG_OST_TITLE, ost_title.as_bytes());
+        }
+        if let Some(publisher) = &tag.publisher {
+            write_text_field(block, SONG_PUBLISHER, publisher.as_bytes());
+        }
+        block[SONG_OST_DISC] = tag.ost_disc.unwrap_or(0);
+        block[SONG_OST_TRACK..SONG_OST_TRACK + 2]
+            .copy_from_slice(&tag.ost_track.unwrap_or(0).to_le_bytes());
+        block[SONG_COPYRIGHT_YEAR..SONG_COPYRIGHT_YEAR + 2]
+            .copy_from_slice(&tag.copyright_year.unwrap_or(0).to_le_bytes());
+    }
+}
+
+/// SPC2ファイルの書き出し。書き込んだバイト数を返す
+/// 内容が同じRAMページは1つにまとめて格納する
+pub fn write_spc2_file(songs: &[SPCFile], out: &mut [u8]) -> Option<usize> {
+    if songs.len() > SPC2_MAX_NUM_SONGS {
+        return None;
+    }
+    let pages_offset = SPC2_HEADER_SIZE + songs.len() * SPC2_SONG_BLOCK_SIZE;
+    if out.len() < pages_offset {
+        return None;
+    }
+
+    // ヘッダ
+    out[..SPC2_HEADER_SIZE].fill(0);
+    out[0..5].copy_from_slice(SPC2_SIGNATURE);
+    out[5] = SPC2_MAJOR_VERSION;
+    out[6] = SPC2_MINOR_VERSION;
+    out[7..9].copy_from_slice(&(songs.len() as u16).to_le_bytes());
+
+    let (head, pages) = out.split_at_mut(pages_offset);
+    let mut num_pages = 0;
+    for (song, spc_file) in songs.iter().enumerate() {
+        let offset = SPC2_HEADER_SIZE + song * SPC2_SONG_BLOCK_SIZE;
+        let block = &mut head[offset..offset + SPC2_SONG_BLOCK_SIZE];
+        write_song_block(spc_file, block);
+
+        for (i, page) in spc_file.ram.chunks_exact(SPC2_PAGE_SIZE).enumerate() {
+            // 格納済みのページを探し、無ければ追加
+            let page_index = match pages[..num_pages * SPC2_PAGE_SIZE]
+                .chunks_exact(SPC2_PAGE_SIZE)
+                .position(|stored| stored == page)
+            {
+                Some(index) => index,
+                None => {
+                    let start = num_pages * SPC2_PAGE_SIZE;
+                    if num_pages >= SPC2_MAX_NUM_PAGES || start + SPC2_PAGE_SIZE > pages.len() {
+                        return None;
+                    }
+                    pages[start..start + SPC2_PAGE_SIZE].copy_from_slice(page);
+                    num_pages += 1;
+                    num_pages - 1
+                }
+            };
+            block[SONG_PAGE_INDEX + 2 * i..SONG_PAGE_INDEX + 2 * i + 2]
+                .copy_from_slice(&(page_index as u16).to_le_bytes());
+        }
+    }
+
+    Some(pages_offset + num_pages * SPC2_PAGE_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::vec::Vec;
+
+    fn bench_spc_file() -> SPCFile {
+        let data = std::fs::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/benches/data/Eyes on Me! [2 = Original] [1 = OG Echo].spc"
+        ))
+        .unwrap();
+        parse_spc_file(&data).unwrap()
+    }
+
+    /// RAMの大半を共有する2曲
+    fn two_songs() -> [SPCFile; 2] {
+        let first = bench_spc_file();
+        let mut second = first.clone();
+        second.ram[0x1234] ^= 0xFF;
+        second.ram[0x8000] ^= 0xFF;
+        second.dsp_register[0x0C] = 0x40;
+        second.xram_buffer[0] ^= 0xFF;
+        second.header.set_music_title("Second song");
+        second.header.duration = 123;
+        second.header.fadeout_time = 4567;
+        let mut tag = ExtendedID666::new();
+        tag.ost_title = Some(XID6String::new(b"Soundtrack"));
+        tag.publisher = Some(XID6String::new(b"Publisher"));
+        tag.ost_disc = Some(2);
+        tag.ost_track = Some(0x0500);
+        tag.copyright_year = Some(1999);
+        second.extended_tag = Some(tag);
+        [first, second]
+    }
+
+    fn write(songs: &[SPCFile]) -> Vec<u8> {
+        let mut data = std::vec![0u8; spc2_file_max_size(songs.len())];
+        let size = write_spc2_file(songs, &mut data).unwrap();
+        data.truncate(size);
+        data
+    }
+
+    fn xid6_bytes(string: &Option<XID6String>) -> Option<&[u8]> {
+        string.as_ref().map(|string| string.as_bytes())
+    }
+
+    #[test]
+    fn round_trip_deduplicates_pages() {
+        let songs = two_songs();
+        let data = write(&songs);
+        let spc2 = parse_spc2_file(&data).unwrap();
+        assert_eq!(spc2.num_songs(), 2);
+        assert_eq!(spc2.minor_version, SPC2_MINOR_VERSION);
+
+        // 格納されるページ数は全曲で異なるページの数
+        let unique_pages: HashSet<&[u8]> = songs
+            .iter()
+            .flat_map(|song| song.ram.chunks_exact(SPC2_PAGE_SIZE))
+            .collect();
+        let pages_offset = SPC2_HEADER_SIZE + 2 * SPC2_SONG_BLOCK_SIZE;
+        assert_eq!(
+            data.len() - pages_offset,
+            unique_pages.len() * SPC2_PAGE_SIZE
+        );
+        assert!(unique_pages.len() < 2 * SPC2_NUM_PAGES_PER_SONG);
+
+        for (index, expected) in songs.iter().enumerate() {
+            let song = spc2.song(index).unwrap();
+            assert_eq!(song.ram[..], expected.ram[..]);
+            assert_eq!(song.dsp_register, expected.dsp_register);
+            assert_eq!(song.xram_buffer, expected.xram_buffer);
+            assert_eq!(song.header.music_title, expected.header.music_title);
+            assert_eq!(song.header.game_title, expected.header.game_title);
+            assert_eq!(song.header.composer, expected.header.composer);
+            assert_eq!(song.header.duration, expected.header.duration);
+            assert_eq!(song.header.fadeout_time, expected.header.fadeout_time);
+            assert_eq!(song.header.spc_register.pc, expected.header.spc_register.pc);
+            assert_eq!(song.header.spc_register.sp, expected.header.spc_register.sp);
+            match (&song.extended_tag, &expected.extended_tag) {
+                (None, None) => {}
+                (Some(tag), Some(expected_tag)) => {
+                    assert_eq!(
+                        xid6_bytes(&tag.ost_title),
+                        xid6_bytes(&expected_tag.ost_title)
+                    );
+                    assert_eq!(
+                        xid6_bytes(&tag.publisher),
+                        xid6_bytes(&expected_tag.publisher)
+                    );
+                    assert_eq!(tag.ost_disc, expected_tag.ost_disc);
+                    assert_eq!(tag.ost_track, expected_tag.ost_track);
+                    assert_eq!(tag.copyright_year, expected_tag.copyright_year);
+                }
+                _ => panic!("extended tag mismatch in song {}", index),
+            }
+        }
+        assert!(spc2.song(2).is_none());
+    }
+
+    #[test]
+    fn rejects_out_of_range_page_index() {
+        let mut data = write(&two_songs());
+        let num_pages = (data.len() - SPC2_HEADER_SIZE - 2 * SPC2_SONG_BLOCK_SIZE) / SPC2_PAGE_SIZE;
+        let offset = SPC2_HEADER_SIZE + SPC2_SONG_BLOCK_SIZE + SONG_PAGE_INDEX;
+        data[offset..offset + 2].copy_from_slice(&(num_pages as u16).to_le_bytes());
+        assert!(parse_spc2_file(&data).is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let data = write(&two_songs());
+        assert!(parse_spc2_file(&data[..SPC2_HEADER_SIZE - 1]).is_none());
+        assert!(parse_spc2_file(&data[..SPC2_HEADER_SIZE + SPC2_SONG_BLOCK_SIZE]).is_none());
+    }
+}
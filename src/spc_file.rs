@@ -1,3 +1,4 @@
+use crate::text::*;
 use crate::types::*;
 
 /// SPCファイルタグ
@@ -69,11 +70,68 @@ pub struct SPCFileHeader {
     pub emurator_type: EmuratorType,
 }
 
+/// 拡張ID666の文字列の最大長
+pub const XID6_STRING_MAX_LENGTH: usize = 256;
+
+/// 拡張ID666の文字列
+#[derive(Debug, Clone)]
+pub struct XID6String {
+    /// 文字列データ（終端のヌル文字は含まない）
+    pub data: [u8; XID6_STRING_MAX_LENGTH],
+    /// 文字列長
+    pub length: usize,
+}
+
+/// 拡張ID666(xid6)タグ
+#[derive(Debug, Clone)]
+pub struct ExtendedID666 {
+    /// 曲名
+    pub song_name: Option<XID6String>,
+    /// ゲーム名
+    pub game_name: Option<XID6String>,
+    /// アーティスト名
+    pub artist: Option<XID6String>,
+    /// ダンプした人
+    pub dumper: Option<XID6String>,
+    /// ダンプした日付(yyyymmdd)
+    pub dump_date: Option<u32>,
+    /// 使用したエミュレータ
+    pub emulator: Option<u8>,
+    /// コメント
+    pub comment: Option<XID6String>,
+    /// サウンドトラックのタイトル
+    pub ost_title: Option<XID6String>,
+    /// サウンドトラックのディスク番号
+    pub ost_disc: Option<u8>,
+    /// サウンドトラックのトラック番号（上位8bit：番号、下位8bit：追加の文字）
+    pub ost_track: Option<u16>,
+    /// 発売元
+    pub publisher: Option<XID6String>,
+    /// 著作権年
+    pub copyright_year: Option<u16>,
+    /// イントロの長さ（1/64000秒単位）
+    pub intro_length: Option<u32>,
+    /// ループ1回分の長さ（1/64000秒単位）
+    pub loop_length: Option<u32>,
+    /// 最後のループ後の長さ（1/64000秒単位）
+    pub end_length: Option<u32>,
+    /// フェードアウトの長さ（1/64000秒単位）
+    pub fade_length: Option<u32>,
+    /// ミュートするボイス
+    pub muted_voices: Option<u8>,
+    /// ループ回数
+    pub loop_count: Option<u8>,
+    /// 増幅率（16.16固定小数）
+    pub amplification: Option<u32>,
+}
+
 /// SPCファイル
 #[derive(Debug, Clone)]
 pub struct SPCFile {
     /// SPCファイルヘッダ
     pub header: SPCFileHeader,
+    /// 拡張ID666タグ
+    pub extended_tag: Option<ExtendedID666>,
     /// 64KB RAM
     pub ram: [u8; 65536],
     /// DSPレジスタ
@@ -82,241 +140,1001 @@ pub struct SPCFile {
     pub xram_buffer: [u8; 64],
 }
 
-// 10進文字列からu64を生成
-fn u8array_to_numeric(data: &[u8]) -> Option<u64> {
-    // 末尾のヌル文字を読み飛ばし
-    let mut i = 0;
-    while data[data.len() - 1 - i] == 0 {
-        i += 1;
-        if i == (data.len() - 1) {
-            return None;
+/// ID666ヘッダのフォーマット
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ID666Format {
+    /// バイナリフォーマット
+    Binary,
+    /// テキストフォーマット
+    Text,
+}
+
+/// ヘッダフォーマットを判定した根拠
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ID666FormatReason {
+    /// 日付がスラッシュで区切られている
+    DateSeparator,
+    /// 日付が数字文字列として読める
+    TextDate,
+    /// 日付がバイナリとして妥当な値
+    BinaryDate,
+    /// 演奏時間が数字文字列として読める
+    TextDuration,
+    /// フェードアウト時間が数字文字列として読める
+    TextFadeout,
+    /// 数字文字列の時間情報に加え、エミュレータの種類か作曲者がテキストフォーマットの位置にある
+    TextLayout,
+    /// 判定できる情報が無い（バイナリとみなした）
+    Undetermined,
+}
+
+/// ID666ヘッダのフィールド
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ID666Field {
+    /// タグの種類
+    Tag,
+    /// SPCファイル生成日
+    GenerateDate,
+    /// SPCファイル生成月
+    GenerateMonth,
+    /// SPCファイル生成年
+    GenerateYear,
+    /// 曲の演奏時間
+    Duration,
+    /// フェードアウト時間
+    FadeoutTime,
+    /// エミュレータの種類
+    EmuratorType,
+}
+
+/// 不正なフィールドの情報
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ID666FieldIssue {
+    /// 不正だったフィールド
+    pub field: ID666Field,
+    /// 代わりに設定した値
+    pub substituted: u32,
+}
+
+/// 記録できる不正フィールドの最大数
+pub const MAX_NUM_ID666_FIELD_ISSUES: usize = 8;
+
+/// ID666ヘッダのパース結果の報告
+#[derive(Debug, Clone)]
+pub struct ID666Report {
+    /// 採用したフォーマット
+    pub format: ID666Format,
+    /// フォーマットを判定した根拠
+    pub reason: ID666FormatReason,
+    /// 不正なフィールドの一覧
+    issues: [Option<ID666FieldIssue>; MAX_NUM_ID666_FIELD_ISSUES],
+    /// 不正なフィールド数
+    num_issues: usize,
+}
+
+impl ID666Report {
+    fn new(format: ID666Format, reason: ID666FormatReason) -> Self {
+        Self {
+            format,
+            reason,
+            issues: [None; MAX_NUM_ID666_FIELD_ISSUES],
+            num_issues: 0,
         }
     }
 
-    // 数字文字列の1桁目から読み取り
-    let mut ret = 0;
-    let mut base = 1;
-    while i < data.len() {
-        let chr = data[data.len() - 1 - i];
-        if chr >= b'0' && chr <= b'9' {
-            ret += base * ((chr - b'0') as u64);
-        } else {
-            return None;
+    /// 不正なフィールドを記録
+    fn push_issue(&mut self, field: ID666Field, substituted: u32) {
+        if self.num_issues < MAX_NUM_ID666_FIELD_ISSUES {
+            self.issues[self.num_issues] = Some(ID666FieldIssue { field, substituted });
+            self.num_issues += 1;
         }
-        base *= 10;
-        i += 1;
     }
 
-    Some(ret)
+    /// 不正なフィールドの一覧
+    pub fn issues(&self) -> impl Iterator<Item = &ID666FieldIssue> {
+        self.issues[..self.num_issues].iter().flatten()
+    }
+
+    /// 全てのフィールドが正常に読めたか
+    pub fn is_clean(&self) -> bool {
+        self.num_issues == 0
+    }
 }
 
-// ヘッダがバイナリフォーマットか否（テキスト）か判定
-fn determine_data_format_is_binary(data: &[u8]) -> bool {
-    // 日付情報が存在するか
-    let date_exist = {
-        let mut flag = false;
-        for i in 0..11 {
-            if data[0x9E + i] != 0 {
-                flag = true;
+/// SPCファイルのサイズ（拡張ID666を除く）
+pub const SPC_FILE_SIZE: usize = 0x10200;
+
+/// SPCファイル先頭の識別文字列
+const SPC_FILE_SIGNATURE: &[u8; 33] = b"SNES-SPC700 Sound File Data v0.30";
+
+/// ID666のタグバージョン
+const SPC_FILE_TAG_VERSION: u8 = 30;
+
+impl SPCFileHeader {
+    /// 空のヘッダを作成
+    pub fn new() -> Self {
+        Self {
+            info: *SPC_FILE_SIGNATURE,
+            tag: SPCFileTag::ID666,
+            tag_version: SPC_FILE_TAG_VERSION,
+            spc_register: SPCRegister {
+                a: 0,
+                x: 0,
+                y: 0,
+                sp: 0,
+                pc: 0,
+                psw: 0,
+            },
+            music_title: [0; 32],
+            game_title: [0; 32],
+            creator: [0; 16],
+            comment: [0; 32],
+            generate_date: 0,
+            generate_month: 0,
+            generate_year: 0,
+            duration: 0,
+            fadeout_time: 0,
+            composer: [0; 32],
+            initial_channel_invalid: 0,
+            emurator_type: EmuratorType::Unknown,
+        }
+    }
+
+    /// ヘッダ情報を文字列として取得
+    pub fn info_text(&self) -> DecodedText {
+        decode_text(&self.info)
+    }
+
+    /// 曲のタイトルを文字列として取得
+    pub fn music_title_text(&self) -> DecodedText {
+        decode_text(&self.music_title)
+    }
+
+    /// ゲームのタイトルを文字列として取得
+    pub fn game_title_text(&self) -> DecodedText {
+        decode_text(&self.game_title)
+    }
+
+    /// SPCファイルの作成者を文字列として取得
+    pub fn creator_text(&self) -> DecodedText {
+        decode_text(&self.creator)
+    }
+
+    /// コメントを文字列として取得
+    pub fn comment_text(&self) -> DecodedText {
+        decode_text(&self.comment)
+    }
+
+    /// 作曲者を文字列として取得
+    pub fn composer_text(&self) -> DecodedText {
+        decode_text(&self.composer)
+    }
+
+    /// 曲のタイトルを設定（長い場合は切り捨て）
+    pub fn set_music_title(&mut self, title: impl AsRef<[u8]>) {
+        copy_text_field(&mut self.music_title, title.as_ref());
+    }
+
+    /// ゲームのタイトルを設定（長い場合は切り捨て）
+    pub fn set_game_title(&mut self, title: impl AsRef<[u8]>) {
+        copy_text_field(&mut self.game_title, title.as_ref());
+    }
+
+    /// SPCファイルの作成者を設定（長い場合は切り捨て）
+    pub fn set_creator(&mut self, creator: impl AsRef<[u8]>) {
+        copy_text_field(&mut self.creator, creator.as_ref());
+    }
+
+    /// コメントを設定（長い場合は切り捨て）
+    pub fn set_comment(&mut self, comment: impl AsRef<[u8]>) {
+        copy_text_field(&mut self.comment, comment.as_ref());
+    }
+
+    /// 作曲者を設定（長い場合は切り捨て）
+    pub fn set_composer(&mut self, composer: impl AsRef<[u8]>) {
+        copy_text_field(&mut self.composer, composer.as_ref());
+    }
+}
+
+/// 拡張ID666チャンクの開始位置
+const XID6_CHUNK_OFFSET: usize = SPC_FILE_SIZE;
+
+/// 拡張ID666のサブチャンクID
+const XID6_ID_SONG_NAME: u8 = 0x01;
+const XID6_ID_GAME_NAME: u8 = 0x02;
+const XID6_ID_ARTIST: u8 = 0x03;
+const XID6_ID_DUMPER: u8 = 0x04;
+const XID6_ID_DUMP_DATE: u8 = 0x05;
+const XID6_ID_EMULATOR: u8 = 0x06;
+const XID6_ID_COMMENT: u8 = 0x07;
+const XID6_ID_OST_TITLE: u8 = 0x10;
+const XID6_ID_OST_DISC: u8 = 0x11;
+const XID6_ID_OST_TRACK: u8 = 0x12;
+const XID6_ID_PUBLISHER: u8 = 0x13;
+const XID6_ID_COPYRIGHT_YEAR: u8 = 0x14;
+const XID6_ID_INTRO_LENGTH: u8 = 0x30;
+const XID6_ID_LOOP_LENGTH: u8 = 0x31;
+const XID6_ID_END_LENGTH: u8 = 0x32;
+const XID6_ID_FADE_LENGTH: u8 = 0x33;
+const XID6_ID_MUTED_VOICES: u8 = 0x34;
+const XID6_ID_LOOP_COUNT: u8 = 0x35;
+const XID6_ID_AMPLIFICATION: u8 = 0x36;
+
+/// 拡張ID666のサブチャンクのデータ型
+const XID6_TYPE_DATA: u8 = 0x00;
+const XID6_TYPE_STRING: u8 = 0x01;
+const XID6_TYPE_INTEGER: u8 = 0x04;
+
+impl XID6String {
+    /// バイト列から作成（ヌル文字以降は無視、最大長を超える部分は切り捨て）
+    pub fn new(data: &[u8]) -> Self {
+        let mut ret = Self {
+            data: [0; XID6_STRING_MAX_LENGTH],
+            length: 0,
+        };
+        for &byte in data.iter().take(XID6_STRING_MAX_LENGTH) {
+            if byte == 0 {
                 break;
             }
+            ret.data[ret.length] = byte;
+            ret.length += 1;
         }
-        flag
-    };
+        ret
+    }
+
+    /// 文字列のバイト列
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.length]
+    }
+
+    /// 文字コードを推定して文字列として取得
+    pub fn to_text(&self) -> DecodedText {
+        decode_text(self.as_bytes())
+    }
+}
+
+impl ExtendedID666 {
+    /// 空のタグを作成
+    pub fn new() -> Self {
+        Self {
+            song_name: None,
+            game_name: None,
+            artist: None,
+            dumper: None,
+            dump_date: None,
+            emulator: None,
+            comment: None,
+            ost_title: None,
+            ost_disc: None,
+            ost_track: None,
+            publisher: None,
+            copyright_year: None,
+            intro_length: None,
+            loop_length: None,
+            end_length: None,
+            fade_length: None,
+            muted_voices: None,
+            loop_count: None,
+            amplification: None,
+        }
+    }
+}
+
+impl Default for ExtendedID666 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 文字列フィールドへのコピー（余りはヌル文字で埋める）
+fn copy_text_field(field: &mut [u8], text: &[u8]) {
+    let length = text.len().min(field.len());
+    field.fill(0);
+    field[..length].copy_from_slice(&text[..length]);
+}
+
+impl Default for SPCFileHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 数字文字列の読み取り（ヌル文字以降と前後の空白は無視）
+/// 空の場合はSome(None)、数字以外を含む場合はNoneを返す
+fn u8array_to_numeric(data: &[u8]) -> Option<Option<u64>> {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    let text = data[..end].trim_ascii();
+    if text.is_empty() {
+        return Some(None);
+    }
+
+    let mut ret = 0u64;
+    for &chr in text.iter() {
+        if !chr.is_ascii_digit() {
+            return None;
+        }
+        ret = ret * 10 + (chr - b'0') as u64;
+    }
+
+    Some(Some(ret))
+}
+
+/// 数字文字列として読めるか（空は除く）
+fn is_numeric_text(data: &[u8]) -> bool {
+    matches!(u8array_to_numeric(data), Some(Some(_)))
+}
+
+/// 領域に0以外のデータが存在するか
+fn data_exist(data: &[u8]) -> bool {
+    data.iter().any(|&b| b != 0)
+}
 
-    if date_exist {
+/// 数値からエミュレータの種類を取得
+pub(crate) fn emurator_type_from_u8(value: u8) -> Option<EmuratorType> {
+    match value {
+        0x00 => Some(EmuratorType::Unknown),
+        0x01 => Some(EmuratorType::ZSNES),
+        0x02 => Some(EmuratorType::Snes9x),
+        0x03 => Some(EmuratorType::ZST2SPC),
+        0x04 => Some(EmuratorType::Other),
+        0x05 => Some(EmuratorType::SNEShout),
+        0x06 => Some(EmuratorType::ZSNESW),
+        0x07 => Some(EmuratorType::Snes9xpp),
+        0x08 => Some(EmuratorType::SNESGT),
+        _ => None,
+    }
+}
+
+// ヘッダがバイナリフォーマットか否（テキスト）か判定
+fn determine_data_format(data: &[u8]) -> (ID666Format, ID666FormatReason) {
+    // 日付情報が存在するか
+    if data_exist(&data[0x9E..0x9E + 11]) {
         // 日付を区切るスラッシュがあればテキスト
         if data[0x9E + 2] == b'/' && data[0x9E + 5] == b'/' {
-            return false;
+            return (ID666Format::Text, ID666FormatReason::DateSeparator);
         }
         // 日付が文字としてパースできればテキスト
-        if let Some(_) = u8array_to_numeric(&data[0x9E..0x9E + 2]) {
-            return false;
+        if is_numeric_text(&data[0x9E..0x9E + 2])
+            || is_numeric_text(&data[0x9E + 3..0x9E + 5])
+            || is_numeric_text(&data[0x9E + 6..0x9E + 11])
+        {
+            return (ID666Format::Text, ID666FormatReason::TextDate);
         }
-        if let Some(_) = u8array_to_numeric(&data[0x9E + 3..0x9E + 5]) {
-            return false;
-        }
-        if let Some(_) = u8array_to_numeric(&data[0x9E + 6..0x9E + 11]) {
-            return false;
+        // バイナリの日付として妥当で、年以降の未使用領域が空ならバイナリ
+        let date = data[0x9E];
+        let month = data[0x9F];
+        if (1..=31).contains(&date) && (1..=12).contains(&month) && !data_exist(&data[0xA2..0xA9]) {
+            return (ID666Format::Binary, ID666FormatReason::BinaryDate);
         }
     }
 
-    // 演奏時間が存在するか
-    let duration_exist = {
-        let mut flag = false;
-        for i in 0..3 {
-            if data[0xA9 + i] != 0 {
-                flag = true;
-                break;
-            }
-        }
-        flag
-    };
+    let duration = &data[0xA9..0xA9 + 3];
+    let fadeout = &data[0xAC..0xAC + 5];
 
-    if duration_exist {
-        // 演奏時間が文字としてパースできればテキスト
-        if let Some(_) = u8array_to_numeric(&data[0xA9..0xA9 + 3]) {
-            return false;
-        }
+    // 演奏時間が2桁以上の文字としてパースできればテキスト
+    // （バイナリとして読むと12336秒以上となり現実的でない）
+    if is_numeric_text(duration) && duration[1] != 0 {
+        return (ID666Format::Text, ID666FormatReason::TextDuration);
     }
 
-    // フェードアウト時間が存在するか
-    let fadeout_exist = {
-        let mut flag = false;
-        for i in 0..5 {
-            if data[0xAC + i] != 0 {
-                flag = true;
-                break;
-            }
-        }
-        flag
-    };
+    // フェードアウト時間が4桁以上の文字としてパースできればテキスト
+    // （バイナリのフェードアウト時間は3バイトで、0xAFは使われない）
+    if is_numeric_text(fadeout) && fadeout[3] != 0 {
+        return (ID666Format::Text, ID666FormatReason::TextFadeout);
+    }
 
-    if fadeout_exist {
-        // フェードアウト時間が文字としてパースできればテキスト
-        if let Some(_) = u8array_to_numeric(&data[0xAC..0xAC + 5]) {
-            return false;
-        }
+    // 桁数の少ない数字文字列はバイナリの値（例：48-57秒の演奏時間）とも読めるため、
+    // エミュレータの種類が数字か、作曲者がテキストフォーマットの位置から始まる場合のみテキストとする
+    if (is_numeric_text(duration) || is_numeric_text(fadeout))
+        && (data[0xD2].is_ascii_digit() || (data[0xB0] == 0 && data[0xB1] != 0))
+    {
+        return (ID666Format::Text, ID666FormatReason::TextLayout);
     }
 
     // 判定不可能な場合はバイナリとする
-    true
+    (ID666Format::Binary, ID666FormatReason::Undetermined)
+}
+
+/// テキストフォーマットの数値フィールドの読み取り
+/// 読めない・範囲外の値は0として報告に記録
+fn parse_text_field(data: &[u8], max: u64, field: ID666Field, report: &mut ID666Report) -> u64 {
+    match u8array_to_numeric(data) {
+        Some(Some(value)) if value <= max => value,
+        Some(None) => 0,
+        _ => {
+            report.push_issue(field, 0);
+            0
+        }
+    }
 }
 
 /// SPCファイルヘッダのパース
-fn parse_spc_header(data: &[u8]) -> Option<SPCFileHeader> {
+/// 不正なフィールドは既定値で置き換え、その内容を報告として返す
+fn parse_spc_header(data: &[u8]) -> Option<(SPCFileHeader, ID666Report)> {
     // サイズチェック
     if data.len() < 256 {
         return None;
     }
 
-    // バイナリ/テキストフォーマット判定してからパース
-    if determine_data_format_is_binary(data) {
-        Some(SPCFileHeader {
-            info: data[0..33].try_into().unwrap(),
-            tag: if data[0x23] == 0x1A {
-                SPCFileTag::ID666
-            } else {
+    // バイナリ/テキストフォーマット判定
+    let (format, reason) = determine_data_format(data);
+    let mut report = ID666Report::new(format, reason);
+
+    // フォーマットに依らない部分
+    let mut header = SPCFileHeader {
+        info: data[0..33].try_into().unwrap(),
+        tag: match data[0x23] {
+            0x1A => SPCFileTag::ID666,
+            0x1B => SPCFileTag::Other,
+            _ => {
+                report.push_issue(ID666Field::Tag, SPCFileTag::Other as u32);
                 SPCFileTag::Other
-            },
-            tag_version: data[0x24],
-            spc_register: SPCRegister {
-                pc: make_u16_from_u8(&data[0x25..0x27]),
-                a: data[0x27],
-                x: data[0x28],
-                y: data[0x29],
-                psw: data[0x2A],
-                sp: data[0x2B],
-            },
-            music_title: data[0x2E..0x2E + 32].try_into().unwrap(),
-            game_title: data[0x4E..0x4E + 32].try_into().unwrap(),
-            creator: data[0x6E..0x6E + 16].try_into().unwrap(),
-            comment: data[0x7E..0x7E + 32].try_into().unwrap(),
-            generate_date: data[0x9E],
-            generate_month: data[0x9F],
-            generate_year: make_u16_from_u8(&data[0xA0..0xA2]),
-            duration: make_u16_from_u8(&data[0xA9..0xAB]),
-            fadeout_time: ((data[0xAC] as u32) << 0)
+            }
+        },
+        tag_version: data[0x24],
+        spc_register: SPCRegister {
+            pc: make_u16_from_u8(&data[0x25..0x27]),
+            a: data[0x27],
+            x: data[0x28],
+            y: data[0x29],
+            psw: data[0x2A],
+            sp: data[0x2B],
+        },
+        music_title: data[0x2E..0x2E + 32].try_into().unwrap(),
+        game_title: data[0x4E..0x4E + 32].try_into().unwrap(),
+        creator: data[0x6E..0x6E + 16].try_into().unwrap(),
+        comment: data[0x7E..0x7E + 32].try_into().unwrap(),
+        ..SPCFileHeader::new()
+    };
+
+    match format {
+        ID666Format::Binary => {
+            header.generate_date = data[0x9E];
+            if header.generate_date > 31 {
+                report.push_issue(ID666Field::GenerateDate, 0);
+                header.generate_date = 0;
+            }
+            header.generate_month = data[0x9F];
+            if header.generate_month > 12 {
+                report.push_issue(ID666Field::GenerateMonth, 0);
+                header.generate_month = 0;
+            }
+            header.generate_year = make_u16_from_u8(&data[0xA0..0xA2]);
+            if header.generate_year > 9999 {
+                report.push_issue(ID666Field::GenerateYear, 0);
+                header.generate_year = 0;
+            }
+            header.duration = make_u16_from_u8(&data[0xA9..0xAB]);
+            header.fadeout_time = ((data[0xAC] as u32) << 0)
                 | ((data[0xAD] as u32) << 8)
-                | ((data[0xAE] as u32) << 16),
-            composer: data[0xB0..0xB0 + 32].try_into().unwrap(),
-            initial_channel_invalid: data[0xD0],
-            emurator_type: match data[0xD1] {
-                0x00 => EmuratorType::Unknown,
-                0x01 => EmuratorType::ZSNES,
-                0x02 => EmuratorType::Snes9x,
-                0x03 => EmuratorType::ZST2SPC,
-                0x04 => EmuratorType::Other,
-                0x05 => EmuratorType::SNEShout,
-                0x06 => EmuratorType::ZSNESW,
-                0x07 => EmuratorType::Snes9xpp,
-                0x08 => EmuratorType::SNESGT,
-                _ => {
-                    return None;
+                | ((data[0xAE] as u32) << 16);
+            header.composer = data[0xB0..0xB0 + 32].try_into().unwrap();
+            header.initial_channel_invalid = data[0xD0];
+            header.emurator_type = match emurator_type_from_u8(data[0xD1]) {
+                Some(emurator_type) => emurator_type,
+                None => {
+                    report.push_issue(ID666Field::EmuratorType, EmuratorType::Unknown as u32);
+                    EmuratorType::Unknown
                 }
-            },
-        })
-    } else {
-        Some(SPCFileHeader {
-            info: data[0..33].try_into().unwrap(),
-            tag: if data[0x23] == 0x1A {
-                SPCFileTag::ID666
-            } else {
-                SPCFileTag::Other
-            },
-            tag_version: data[0x24],
-            spc_register: SPCRegister {
-                pc: make_u16_from_u8(&data[0x25..0x27]),
-                a: data[0x27],
-                x: data[0x28],
-                y: data[0x29],
-                psw: data[0x2A],
-                sp: data[0x2B],
-            },
-            music_title: data[0x2E..0x2E + 32].try_into().unwrap(),
-            game_title: data[0x4E..0x4E + 32].try_into().unwrap(),
-            creator: data[0x6E..0x6E + 16].try_into().unwrap(),
-            comment: data[0x7E..0x7E + 32].try_into().unwrap(),
-            generate_date: if let Some(d) = u8array_to_numeric(&data[0x9E + 3..0x9E + 5]) {
-                d as u8
-            } else {
-                0
-            },
-            generate_month: if let Some(m) = u8array_to_numeric(&data[0x9E..0x9E + 2]) {
-                m as u8
-            } else {
-                0
-            },
-            generate_year: if let Some(y) = u8array_to_numeric(&data[0x9E + 6..0x9E + 11]) {
-                y as u16
-            } else {
-                0
-            },
-            duration: if let Some(d) = u8array_to_numeric(&data[0xA9..0xA9 + 3]) {
-                d as u16
-            } else {
-                0
-            },
-            fadeout_time: if let Some(f) = u8array_to_numeric(&data[0xAC..0xAC + 5]) {
-                f as u32
-            } else {
-                0
-            },
-            composer: data[0xB1..0xB1 + 32].try_into().unwrap(),
-            initial_channel_invalid: data[0xD1],
-            emurator_type: match data[0xD2] - b'0' {
-                0x00 => EmuratorType::Unknown,
-                0x01 => EmuratorType::ZSNES,
-                0x02 => EmuratorType::Snes9x,
-                0x03 => EmuratorType::ZST2SPC,
-                0x04 => EmuratorType::Other,
-                0x05 => EmuratorType::SNEShout,
-                0x06 => EmuratorType::ZSNESW,
-                0x07 => EmuratorType::Snes9xpp,
-                0x08 => EmuratorType::SNESGT,
-                _ => {
-                    return None;
+            };
+        }
+        ID666Format::Text => {
+            header.generate_month = parse_text_field(
+                &data[0x9E..0x9E + 2],
+                12,
+                ID666Field::GenerateMonth,
+                &mut report,
+            ) as u8;
+            header.generate_date = parse_text_field(
+                &data[0x9E + 3..0x9E + 5],
+                31,
+                ID666Field::GenerateDate,
+                &mut report,
+            ) as u8;
+            header.generate_year = parse_text_field(
+                &data[0x9E + 6..0x9E + 11],
+                9999,
+                ID666Field::GenerateYear,
+                &mut report,
+            ) as u16;
+            header.duration = parse_text_field(
+                &data[0xA9..0xA9 + 3],
+                999,
+                ID666Field::Duration,
+                &mut report,
+            ) as u16;
+            header.fadeout_time = parse_text_field(
+                &data[0xAC..0xAC + 5],
+                99999,
+                ID666Field::FadeoutTime,
+                &mut report,
+            ) as u32;
+            header.composer = data[0xB1..0xB1 + 32].try_into().unwrap();
+            header.initial_channel_invalid = data[0xD1];
+            // 未設定(ヌル文字)は不明として扱う
+            let emurator = match data[0xD2] {
+                0 => Some(EmuratorType::Unknown),
+                chr if chr.is_ascii_digit() => emurator_type_from_u8(chr - b'0'),
+                _ => None,
+            };
+            header.emurator_type = match emurator {
+                Some(emurator_type) => emurator_type,
+                None => {
+                    report.push_issue(ID666Field::EmuratorType, EmuratorType::Unknown as u32);
+                    EmuratorType::Unknown
                 }
-            },
-        })
+            };
+        }
+    }
+
+    Some((header, report))
+}
+
+/// 拡張ID666チャンクのパース
+/// 壊れたサブチャンクに出会った場合はそこまでの結果を返す
+fn parse_xid6_chunk(data: &[u8]) -> Option<ExtendedID666> {
+    // チャンクヘッダ
+    if data.len() < 8 || &data[0..4] != b"xid6" {
+        return None;
+    }
+    let chunk_size = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+    let chunk = &data[8..(8 + chunk_size).min(data.len())];
+
+    let mut tag = ExtendedID666::new();
+    let mut pos = 0;
+    while pos + 4 <= chunk.len() {
+        let id = chunk[pos];
+        let data_type = chunk[pos + 1];
+        let length = make_u16_from_u8(&chunk[(pos + 2)..(pos + 4)]);
+        pos += 4;
+
+        // ヘッダにデータを含む型以外はデータ部を読む
+        let body = if data_type == XID6_TYPE_DATA {
+            &chunk[0..0]
+        } else {
+            let end = pos + length as usize;
+            if end > chunk.len() {
+                break;
+            }
+            let body = &chunk[pos..end];
+            // データ部は4バイト境界に揃えられている
+            pos = (end + 3) & !3;
+            body
+        };
+        let integer = if data_type == XID6_TYPE_INTEGER && body.len() == 4 {
+            Some(u32::from_le_bytes(body.try_into().unwrap()))
+        } else {
+            None
+        };
+        let string = if data_type == XID6_TYPE_STRING {
+            Some(XID6String::new(body))
+        } else {
+            None
+        };
+
+        match id {
+            XID6_ID_SONG_NAME => tag.song_name = string,
+            XID6_ID_GAME_NAME => tag.game_name = string,
+            XID6_ID_ARTIST => tag.artist = string,
+            XID6_ID_DUMPER => tag.dumper = string,
+            XID6_ID_DUMP_DATE => tag.dump_date = integer,
+            XID6_ID_EMULATOR => tag.emulator = Some(length as u8),
+            XID6_ID_COMMENT => tag.comment = string,
+            XID6_ID_OST_TITLE => tag.ost_title = string,
+            XID6_ID_OST_DISC => tag.ost_disc = Some(length as u8),
+            XID6_ID_OST_TRACK => tag.ost_track = Some(length),
+            XID6_ID_PUBLISHER => tag.publisher = string,
+            XID6_ID_COPYRIGHT_YEAR => tag.copyright_year = Some(length),
+            XID6_ID_INTRO_LENGTH => tag.intro_length = integer,
+            XID6_ID_LOOP_LENGTH => tag.loop_length = integer,
+            XID6_ID_END_LENGTH => tag.end_length = integer,
+            XID6_ID_FADE_LENGTH => tag.fade_length = integer,
+            XID6_ID_MUTED_VOICES => tag.muted_voices = Some(length as u8),
+            XID6_ID_LOOP_COUNT => tag.loop_count = Some(length as u8),
+            XID6_ID_AMPLIFICATION => tag.amplification = integer,
+            _ => {
+                // 未知のIDは読み飛ばす
+            }
+        }
     }
+
+    Some(tag)
 }
 
 /// SPCファイルのパース
 pub fn parse_spc_file(data: &[u8]) -> Option<SPCFile> {
+    parse_spc_file_with_report(data).map(|(spc_file, _)| spc_file)
+}
+
+/// SPCファイルのパース（ヘッダのパース結果の報告付き）
+/// ヘッダに不正なフィールドがあっても既定値で置き換えて読み込む
+pub fn parse_spc_file_with_report(data: &[u8]) -> Option<(SPCFile, ID666Report)> {
     // サイズチェック
-    if data.len() < 66048 {
+    if data.len() < SPC_FILE_SIZE {
         return None;
     }
 
-    if let Some(header) = parse_spc_header(data) {
-        return Some(SPCFile {
+    let (header, report) = parse_spc_header(data)?;
+    Some((
+        SPCFile {
             header: header,
+            extended_tag: parse_xid6_chunk(&data[XID6_CHUNK_OFFSET..]),
             ram: data[0x100..0x100 + 65536].try_into().unwrap(),
             dsp_register: data[0x10100..0x10100 + 128].try_into().unwrap(),
             xram_buffer: data[0x101C0..0x101C0 + 64].try_into().unwrap(),
-        });
+        },
+        report,
+    ))
+}
+
+/// SPCファイルヘッダの書き出し
+/// バイナリで書き出した内容がテキストと判定される場合は、読み戻せるようテキストで書き直す
+fn write_spc_header(header: &SPCFileHeader, format: ID666Format, out: &mut [u8]) {
+    write_spc_header_fields(header, format, out);
+    if format == ID666Format::Binary && determine_data_format(out).0 == ID666Format::Text {
+        write_spc_header_fields(header, ID666Format::Text, out);
+    }
+}
+
+/// 指定したフォーマットでのSPCファイルヘッダのフィールドの書き出し
+fn write_spc_header_fields(header: &SPCFileHeader, format: ID666Format, out: &mut [u8]) {
+    out[..0x100].fill(0);
+    out[0..33].copy_from_slice(&header.info);
+    out[0x21] = 0x1A;
+    out[0x22] = 0x1A;
+    out[0x23] = header.tag.clone() as u8;
+    out[0x24] = header.tag_version;
+    out[0x25] = (header.spc_register.pc & 0xFF) as u8;
+    out[0x26] = (header.spc_register.pc >> 8) as u8;
+    out[0x27] = header.spc_register.a;
+    out[0x28] = header.spc_register.x;
+    out[0x29] = header.spc_register.y;
+    out[0x2A] = header.spc_register.psw;
+    out[0x2B] = header.spc_register.sp;
+    out[0x2E..0x2E + 32].copy_from_slice(&header.music_title);
+    out[0x4E..0x4E + 32].copy_from_slice(&header.game_title);
+    out[0x6E..0x6E + 16].copy_from_slice(&header.creator);
+    out[0x7E..0x7E + 32].copy_from_slice(&header.comment);
+    match format {
+        ID666Format::Binary => {
+            out[0x9E] = header.generate_date;
+            out[0x9F] = header.generate_month;
+            out[0xA0..0xA2].copy_from_slice(&header.generate_year.to_le_bytes());
+            out[0xA9..0xAB].copy_from_slice(&header.duration.to_le_bytes());
+            out[0xAC..0xAF].copy_from_slice(&header.fadeout_time.to_le_bytes()[..3]);
+            out[0xB0..0xB0 + 32].copy_from_slice(&header.composer);
+            out[0xD0] = header.initial_channel_invalid;
+            out[0xD1] = header.emurator_type.clone() as u8;
+        }
+        ID666Format::Text => {
+            // 日付は設定されている場合のみ書き出す
+            if header.generate_date != 0 || header.generate_month != 0 || header.generate_year != 0
+            {
+                write_numeric_text(&mut out[0x9E..0xA0], header.generate_month as u32, true);
+                out[0xA0] = b'/';
+                write_numeric_text(&mut out[0xA1..0xA3], header.generate_date as u32, true);
+                out[0xA3] = b'/';
+                write_numeric_text(&mut out[0xA4..0xA8], header.generate_year as u32, true);
+            }
+            // テキストと判定できるよう演奏時間とフェードアウト時間は常に書き出す
+            write_numeric_text(&mut out[0xA9..0xAC], header.duration as u32, false);
+            write_numeric_text(&mut out[0xAC..0xB1], header.fadeout_time, false);
+            out[0xB1..0xB1 + 32].copy_from_slice(&header.composer);
+            out[0xD1] = header.initial_channel_invalid;
+            out[0xD2] = b'0' + header.emurator_type.clone() as u8;
+        }
+    }
+}
+
+/// 数字文字列の書き出し（桁あふれする場合は最大値に飽和）
+/// zero_fillが偽の場合は左詰めでヌル文字埋めする
+fn write_numeric_text(out: &mut [u8], value: u32, zero_fill: bool) {
+    let max = 10u64.pow(out.len() as u32) - 1;
+    let mut value = (value as u64).min(max);
+    let mut digits = [b'0'; 10];
+    let mut num_digits = 0;
+    while value > 0 || num_digits == 0 {
+        digits[num_digits] = b'0' + (value % 10) as u8;
+        value /= 10;
+        num_digits += 1;
+    }
+    if zero_fill {
+        num_digits = out.len();
+    }
+    out.fill(0);
+    for i in 0..num_digits {
+        out[i] = digits[num_digits - 1 - i];
+    }
+}
+
+/// 拡張ID666チャンクに書き出す文字列の長さ（ヌル文字を含む）
+fn xid6_string_length(string: &XID6String) -> usize {
+    (string.length + 1).min(XID6_STRING_MAX_LENGTH)
+}
+
+/// 拡張ID666チャンクのサイズ（チャンクヘッダを含む）
+pub fn xid6_chunk_size(tag: &ExtendedID666) -> usize {
+    // 文字列：ヘッダ4バイト + 4バイト境界に揃えたデータ
+    let strings: usize = [
+        &tag.song_name,
+        &tag.game_name,
+        &tag.artist,
+        &tag.dumper,
+        &tag.comment,
+        &tag.ost_title,
+        &tag.publisher,
+    ]
+    .into_iter()
+    .flatten()
+    .map(|string| 4 + ((xid6_string_length(string) + 3) & !3))
+    .sum();
+    // 整数：ヘッダ4バイト + 4バイト
+    let num_integers = [
+        tag.dump_date,
+        tag.intro_length,
+        tag.loop_length,
+        tag.end_length,
+        tag.fade_length,
+        tag.amplification,
+    ]
+    .into_iter()
+    .flatten()
+    .count();
+    // ヘッダにデータを含む型：ヘッダ4バイトのみ
+    let num_data = [
+        tag.emulator.is_some(),
+        tag.ost_disc.is_some(),
+        tag.ost_track.is_some(),
+        tag.copyright_year.is_some(),
+        tag.muted_voices.is_some(),
+        tag.loop_count.is_some(),
+    ]
+    .into_iter()
+    .filter(|&exist| exist)
+    .count();
+    8 + strings + 8 * num_integers + 4 * num_data
+}
+
+/// 拡張ID666のサブチャンク書き出し
+struct XID6Writer<'a> {
+    out: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> XID6Writer<'a> {
+    /// ヘッダにデータを含むサブチャンク
+    fn write_data(&mut self, id: u8, value: Option<u16>) {
+        if let Some(value) = value {
+            self.out[self.pos] = id;
+            self.out[self.pos + 1] = XID6_TYPE_DATA;
+            self.out[self.pos + 2..self.pos + 4].copy_from_slice(&value.to_le_bytes());
+            self.pos += 4;
+        }
+    }
+
+    /// 文字列のサブチャンク
+    fn write_string(&mut self, id: u8, string: &Option<XID6String>) {
+        if let Some(string) = string {
+            let length = xid6_string_length(string);
+            self.out[self.pos] = id;
+            self.out[self.pos + 1] = XID6_TYPE_STRING;
+            self.out[self.pos + 2..self.pos + 4].copy_from_slice(&(length as u16).to_le_bytes());
+            self.pos += 4;
+            // 終端のヌル文字と4バイト境界までのパディング
+            let padded_length = (length + 3) & !3;
+            self.out[self.pos..self.pos + padded_length].fill(0);
+            self.out[self.pos..self.pos + length - 1]
+                .copy_from_slice(&string.as_bytes()[..length - 1]);
+            self.pos += padded_length;
+        }
+    }
+
+    /// 整数のサブチャンク
+    fn write_integer(&mut self, id: u8, value: Option<u32>) {
+        if let Some(value) = value {
+            self.out[self.pos] = id;
+            self.out[self.pos + 1] = XID6_TYPE_INTEGER;
+            self.out[self.pos + 2..self.pos + 4].copy_from_slice(&4u16.to_le_bytes());
+            self.out[self.pos + 4..self.pos + 8].copy_from_slice(&value.to_le_bytes());
+            self.pos += 8;
+        }
+    }
+}
+
+/// 拡張ID666チャンクの書き出し。書き込んだバイト数を返す
+fn write_xid6_chunk(tag: &ExtendedID666, out: &mut [u8]) -> Option<usize> {
+    let size = xid6_chunk_size(tag);
+    if out.len() < size {
+        return None;
+    }
+
+    out[0..4].copy_from_slice(b"xid6");
+    out[4..8].copy_from_slice(&((size - 8) as u32).to_le_bytes());
+    let mut writer = XID6Writer { out, pos: 8 };
+    writer.write_string(XID6_ID_SONG_NAME, &tag.song_name);
+    writer.write_string(XID6_ID_GAME_NAME, &tag.game_name);
+    writer.write_string(XID6_ID_ARTIST, &tag.artist);
+    writer.write_string(XID6_ID_DUMPER, &tag.dumper);
+    writer.write_integer(XID6_ID_DUMP_DATE, tag.dump_date);
+    writer.write_data(XID6_ID_EMULATOR, tag.emulator.map(|v| v as u16));
+    writer.write_string(XID6_ID_COMMENT, &tag.comment);
+    writer.write_string(XID6_ID_OST_TITLE, &tag.ost_title);
+    writer.write_data(XID6_ID_OST_DISC, tag.ost_disc.map(|v| v as u16));
+    writer.write_data(XID6_ID_OST_TRACK, tag.ost_track);
+    writer.write_string(XID6_ID_PUBLISHER, &tag.publisher);
+    writer.write_data(XID6_ID_COPYRIGHT_YEAR, tag.copyright_year);
+    writer.write_integer(XID6_ID_INTRO_LENGTH, tag.intro_length);
+    writer.write_integer(XID6_ID_LOOP_LENGTH, tag.loop_length);
+    writer.write_integer(XID6_ID_END_LENGTH, tag.end_length);
+    writer.write_integer(XID6_ID_FADE_LENGTH, tag.fade_length);
+    writer.write_data(XID6_ID_MUTED_VOICES, tag.muted_voices.map(|v| v as u16));
+    writer.write_data(XID6_ID_LOOP_COUNT, tag.loop_count.map(|v| v as u16));
+    writer.write_integer(XID6_ID_AMPLIFICATION, tag.amplification);
+
+    Some(writer.pos)
+}
+
+/// SPCファイルを書き出したときのサイズ（拡張ID666を含む）
+pub fn spc_file_size(spc_file: &SPCFile) -> usize {
+    SPC_FILE_SIZE + spc_file.extended_tag.as_ref().map_or(0, xid6_chunk_size)
+}
+
+/// SPCファイルの書き出し（ヘッダはバイナリフォーマット）。書き込んだバイト数を返す
+/// バイナリではテキストと区別できない値の場合はテキストフォーマットで書き出す
+pub fn write_spc_file(spc_file: &SPCFile, out: &mut [u8]) -> Option<usize> {
+    write_spc_file_with_format(spc_file, ID666Format::Binary, out)
+}
+
+/// ヘッダのフォーマットを指定したSPCファイルの書き出し。書き込んだバイト数を返す
+/// 拡張ID666タグがあればファイル末尾に拡張ID666チャンクを書き出す
+pub fn write_spc_file_with_format(
+    spc_file: &SPCFile,
+    format: ID666Format,
+    out: &mut [u8],
+) -> Option<usize> {
+    // サイズチェック
+    if out.len() < spc_file_size(spc_file) {
+        return None;
+    }
+
+    write_spc_header(&spc_file.header, format, &mut out[..0x100]);
+    out[0x100..0x100 + 65536].copy_from_slice(&spc_file.ram);
+    out[0x10100..0x10100 + 128].copy_from_slice(&spc_file.dsp_register);
+    out[0x10180..0x101C0].fill(0);
+    out[0x101C0..0x101C0 + 64].copy_from_slice(&spc_file.xram_buffer);
+
+    let mut size = SPC_FILE_SIZE;
+    if let Some(tag) = &spc_file.extended_tag {
+        size += write_xid6_chunk(tag, &mut out[XID6_CHUNK_OFFSET..])?;
+    }
+
+    Some(size)
+}
+
+/// SPCファイルのタグのみを書き換える。書き換え後のファイルサイズを返す
+/// RAM・DSPレジスタ等のヘッダ以外の領域はそのまま残し、拡張ID666チャンクはextended_tagで置き換える（Noneなら削除）
+/// dataには元のSPCファイルが格納されていて、新しい拡張ID666チャンクを含むサイズが確保されている必要がある
+pub fn rewrite_spc_tags(
+    data: &mut [u8],
+    header: &SPCFileHeader,
+    extended_tag: Option<&ExtendedID666>,
+    format: ID666Format,
+) -> Option<usize> {
+    // サイズチェック
+    let size = SPC_FILE_SIZE + extended_tag.map_or(0, xid6_chunk_size);
+    if data.len() < size {
+        return None;
     }
 
-    None
+    write_spc_header(header, format, &mut data[..0x100]);
+    if let Some(tag) = extended_tag {
+        write_xid6_chunk(tag, &mut data[XID6_CHUNK_OFFSET..])?;
+    }
+
+    Some(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ヘッダを書き出して読み戻す
+    fn write_and_parse(
+        header: &SPCFileHeader,
+        format: ID666Format,
+    ) -> (SPCFileHeader, ID666Report) {
+        let spc_file = SPCFile {
+            header: header.clone(),
+            extended_tag: None,
+            ram: [0; 65536],
+            dsp_register: [0; 128],
+            xram_buffer: [0; 64],
+        };
+        let mut data = std::vec![0u8; SPC_FILE_SIZE];
+        assert_eq!(
+            write_spc_file_with_format(&spc_file, format, &mut data),
+            Some(SPC_FILE_SIZE)
+        );
+        let (parsed, report) = parse_spc_file_with_report(&data).unwrap();
+        (parsed.header, report)
+    }
+
+    /// 書き出したヘッダが同じ内容で読み戻せるか確認
+    fn assert_round_trip(header: &SPCFileHeader, format: ID666Format) {
+        let (parsed, report) = write_and_parse(header, format);
+        let context = (format, header.duration, header.fadeout_time);
+        assert_eq!(report.issues().count(), 0, "{:?}", context);
+        assert_eq!(parsed.duration, header.duration, "{:?}", context);
+        assert_eq!(parsed.fadeout_time, header.fadeout_time, "{:?}", context);
+        assert_eq!(parsed.composer, header.composer, "{:?}", context);
+        assert_eq!(parsed.generate_date, header.generate_date, "{:?}", context);
+        assert_eq!(
+            parsed.generate_month, header.generate_month,
+            "{:?}",
+            context
+        );
+        assert_eq!(parsed.generate_year, header.generate_year, "{:?}", context);
+        assert_eq!(
+            parsed.initial_channel_invalid, header.initial_channel_invalid,
+            "{:?}",
+            context
+        );
+        assert_eq!(
+            parsed.emurator_type.clone() as u8,
+            header.emurator_type.clone() as u8,
+            "{:?}",
+            context
+        );
+    }
+
+    fn test_headers() -> std::vec::Vec<SPCFileHeader> {
+        let mut headers = std::vec::Vec::new();
+        for duration in (0..=60).chain([120, 300, 999]) {
+            for fadeout_time in [0, 48, 500, 10000] {
+                for composer in [&b""[..], b"composer"] {
+                    for dated in [false, true] {
+                        let mut header = SPCFileHeader::new();
+                        header.duration = duration;
+                        header.fadeout_time = fadeout_time;
+                        header.composer[..composer.len()].copy_from_slice(composer);
+                        if dated {
+                            header.generate_date = 24;
+                            header.generate_month = 12;
+                            header.generate_year = 1999;
+                        }
+                        header.emurator_type = EmuratorType::Snes9x;
+                        headers.push(header);
+                    }
+                }
+            }
+        }
+        headers
+    }
+
+    #[test]
+    fn binary_header_round_trip() {
+        for header in test_headers() {
+            assert_round_trip(&header, ID666Format::Binary);
+        }
+    }
+
+    #[test]
+    fn text_header_round_trip() {
+        for header in test_headers() {
+            assert_round_trip(&header, ID666Format::Text);
+        }
+    }
+
+    /// 48-57秒の演奏時間（1桁の数字と読めるバイト列）でもバイナリのまま読み戻せる
+    #[test]
+    fn binary_duration_looks_like_digit() {
+        for duration in 48..=57 {
+            let mut header = SPCFileHeader::new();
+            header.duration = duration;
+            header.fadeout_time = 10000;
+            let (parsed, report) = write_and_parse(&header, ID666Format::Binary);
+            assert_eq!(report.format, ID666Format::Binary);
+            assert_eq!(parsed.duration, duration);
+        }
+    }
 }
@@ -0,0 +1,155 @@
+use crate::spc_file::XID6_STRING_MAX_LENGTH;
+use core::fmt;
+use encoding_rs::{DecoderResult, SHIFT_JIS};
+
+/// デコード済み文字列の最大バイト長
+/// 1バイトが最大3バイトのUTF-8に変換される（半角カナ・置換文字）ため、最長のフィールドの3倍とする
+pub const DECODED_TEXT_MAX_LENGTH: usize = 3 * XID6_STRING_MAX_LENGTH;
+
+/// 文字コード
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+    /// UTF-8（ASCIIを含む）
+    UTF8,
+    /// Shift-JIS
+    ShiftJIS,
+    /// Latin-1(ISO-8859-1)
+    Latin1,
+}
+
+/// デコード済み文字列
+#[derive(Clone)]
+pub struct DecodedText {
+    /// UTF-8文字列データ
+    data: [u8; DECODED_TEXT_MAX_LENGTH],
+    /// 文字列長
+    length: usize,
+    /// デコードに使用した文字コード
+    pub encoding: TextEncoding,
+}
+
+impl DecodedText {
+    /// 文字列として参照
+    pub fn as_str(&self) -> &str {
+        // UTF-8として正しいデータのみを格納している
+        core::str::from_utf8(&self.data[..self.length]).unwrap_or("")
+    }
+
+    /// 空か否か
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// 1文字追加（入りきらない場合はfalse）
+    fn push(&mut self, chr: char) -> bool {
+        let length = chr.len_utf8();
+        if self.length + length > DECODED_TEXT_MAX_LENGTH {
+            return false;
+        }
+        chr.encode_utf8(&mut self.data[self.length..]);
+        self.length += length;
+        true
+    }
+}
+
+impl fmt::Display for DecodedText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for DecodedText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} ({:?})", self.as_str(), self.encoding)
+    }
+}
+
+/// ヌル文字以降と末尾の空白を取り除く
+fn trim_text(data: &[u8]) -> &[u8] {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    let mut data = &data[..end];
+    while let Some((&last, rest)) = data.split_last() {
+        // 空白と埋め草(0xFF)を除去
+        if last == b' ' || last == 0xFF {
+            data = rest;
+        } else {
+            break;
+        }
+    }
+    data
+}
+
+/// Shift-JISとして正しいか判定
+fn is_valid_shift_jis(data: &[u8]) -> bool {
+    let mut decoder = SHIFT_JIS.new_decoder_without_bom_handling();
+    let mut buffer = [0u8; 64];
+    let mut src = data;
+    loop {
+        let (result, read, _) = decoder.decode_to_utf8_without_replacement(src, &mut buffer, true);
+        src = &src[read..];
+        match result {
+            DecoderResult::InputEmpty => return true,
+            DecoderResult::OutputFull => {}
+            DecoderResult::Malformed(_, _) => return false,
+        }
+    }
+}
+
+/// 文字コードを推定
+/// ASCII・UTF-8として正しければUTF-8、Shift-JISとして正しければShift-JIS、どちらでもなければLatin-1とする
+pub fn detect_text_encoding(data: &[u8]) -> TextEncoding {
+    let data = trim_text(data);
+    if core::str::from_utf8(data).is_ok() {
+        TextEncoding::UTF8
+    } else if is_valid_shift_jis(data) {
+        TextEncoding::ShiftJIS
+    } else {
+        TextEncoding::Latin1
+    }
+}
+
+/// 指定した文字コードで文字列をデコード（ヌル文字以降・末尾の空白は除去）
+/// 不正なバイト列は置換文字に変換する
+pub fn decode_text_with_encoding(data: &[u8], encoding: TextEncoding) -> DecodedText {
+    let data = trim_text(data);
+    let mut text = DecodedText {
+        data: [0; DECODED_TEXT_MAX_LENGTH],
+        length: 0,
+        encoding,
+    };
+
+    match encoding {
+        TextEncoding::UTF8 => {
+            for chunk in data.utf8_chunks() {
+                for chr in chunk.valid().chars() {
+                    if !text.push(chr) {
+                        return text;
+                    }
+                }
+                if !chunk.invalid().is_empty() && !text.push(char::REPLACEMENT_CHARACTER) {
+                    return text;
+                }
+            }
+        }
+        TextEncoding::ShiftJIS => {
+            let mut decoder = SHIFT_JIS.new_decoder_without_bom_handling();
+            // 出力が尽きた場合も文字の途中で切れることはない
+            let (_, _, written, _) = decoder.decode_to_utf8(data, &mut text.data, true);
+            text.length = written;
+        }
+        TextEncoding::Latin1 => {
+            for &byte in data.iter() {
+                if !text.push(byte as char) {
+                    return text;
+                }
+            }
+        }
+    }
+
+    text
+}
+
+/// 文字コードを推定して文字列をデコード（ヌル文字以降・末尾の空白は除去）
+pub fn decode_text(data: &[u8]) -> DecodedText {
+    decode_text_with_encoding(data, detect_text_encoding(data))
+}
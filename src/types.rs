@@ -1,3 +1,5 @@
+use crate::snapshot::Snapshot;
+
 /// 1度に出力できる最大のMIDIメッセージ数
 pub const MAX_NUM_MIDI_OUTPUT_MESSAGES: usize = 100;
 
@@ -389,19 +391,44 @@ pub struct MIDIOutput {
     pub num_messages: usize,
 }
 
+/// エミュレータのエラー
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SPCError {
+    /// テストレジスタへの書き込み
+    WriteToTestRegister { value: u8 },
+    /// タイマー出力(TxOUT)レジスタへの書き込み
+    WriteToTimerOutputRegister { address: usize, value: u8 },
+    /// 未対応のDSPアドレスの読み込み
+    UnsupportedDSPAddress { address: u8 },
+    /// IPL転送プロトコルの応答待ちがタイムアウト
+    IPLHandshakeTimeout { port: u8, expected: u8 },
+    /// ポート書き込みの予約キューが満杯
+    PortEventQueueFull,
+    /// ポート書き込みの予約が時刻順でない（直前の予約の時刻より前）
+    PortEventOutOfOrder { cycle: u64, last_cycle: u64 },
+}
+
+/// DSPの1サンプルあたりのステップ数（SPCの1サイクルで1ステップ進む）
+pub const DSP_STEPS_PER_SAMPLE: u8 = 32;
+
 /// SPCのDSPトレイト
-pub trait SPCDSP {
+pub trait SPCDSP: Snapshot {
     type Output;
     /// コンストラクタ
     fn new() -> Self;
     /// レジスタの初期化
     fn initialize(&mut self, ram: &mut [u8], dsp_register: &[u8; 128]);
+    /// 厳格モードの設定（無効時は実機と同様にエラーとなる操作を無視する）
+    fn set_strict_mode(&mut self, strict: bool);
     /// レジスタに書き込み
-    fn write_register(&mut self, ram: &[u8], address: u8, value: u8);
+    fn write_register(&mut self, ram: &[u8], address: u8, value: u8) -> Result<(), SPCError>;
     /// レジスタから読み出し
-    fn read_register(&self, ram: &[u8], address: u8) -> u8;
+    fn read_register(&self, ram: &[u8], address: u8) -> Result<u8, SPCError>;
     /// 定期処理
     fn tick(&mut self, ram: &mut [u8]) -> Option<Self::Output>;
+    /// サンプル内の指定ステップの直前まで処理を進める（レジスタの読み書きの前に呼ばれる）
+    /// サンプル単位で処理するDSPでは何もしない
+    fn synchronize(&mut self, _ram: &mut [u8], _step: u8) {}
 }
 
 /// メモリ上にあるデータから16bitデータを読みだす
@@ -0,0 +1,496 @@
+use crate::renderer::*;
+use crate::sdsp::SDSPStems;
+use crate::spc_file::*;
+
+/// 出力チャンネル数
+const NUM_CHANNELS: u16 = 2;
+/// ds64チャンクのデータサイズ（RIFFサイズ・dataサイズ・サンプル数・テーブル長）
+const DS64_CHUNK_SIZE: usize = 28;
+/// 書き込みバッファのサイズ
+const WRITE_BUFFER_SIZE: usize = 4096;
+/// INFOチャンクに書き込む文字列の最大長
+const INFO_TEXT_MAX_LENGTH: usize = 256;
+/// ステムの書き出しで一度に処理するサンプル数
+const STEM_BLOCK_SIZE: usize = 1024;
+/// ステムの数（8ボイスとエコー成分）
+pub const NUM_STEMS: usize = 9;
+/// RIFFヘッダの位置
+const RIFF_HEADER_POSITION: u64 = 0;
+/// JUNK(ds64)チャンクの位置
+const DS64_CHUNK_POSITION: u64 = 12;
+
+/// 文字列を最大バイト長以下に切り詰める（マルチバイト文字の途中では切らない）
+fn truncate_text(text: &str, max_length: usize) -> &str {
+    let mut length = text.len().min(max_length);
+    while !text.is_char_boundary(length) {
+        length -= 1;
+    }
+    &text[..length]
+}
+
+/// WAVの書き出し先
+pub trait WavOutput {
+    type Error;
+    /// 末尾にデータを書き込む
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+    /// 指定位置のデータを上書きする（ヘッダのサイズ情報の更新に使用）
+    fn write_at(&mut self, position: u64, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// サンプルのフォーマット
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WavSampleFormat {
+    /// 16bit整数PCM
+    PCM16,
+    /// 24bit整数PCM
+    PCM24,
+    /// 32bit浮動小数点数
+    Float32,
+}
+
+impl WavSampleFormat {
+    /// 1サンプルあたりのバイト数
+    fn bytes_per_sample(&self) -> usize {
+        match self {
+            WavSampleFormat::PCM16 => 2,
+            WavSampleFormat::PCM24 => 3,
+            WavSampleFormat::Float32 => 4,
+        }
+    }
+
+    /// フォーマットタグ
+    fn format_tag(&self) -> u16 {
+        match self {
+            WavSampleFormat::PCM16 | WavSampleFormat::PCM24 => 1,
+            WavSampleFormat::Float32 => 3,
+        }
+    }
+}
+
+/// WAV(RF64)ファイルの逐次書き出し
+/// サイズが4GBを超えた場合はfinalize時にRF64形式に切り替える
+pub struct WavWriter<W: WavOutput> {
+    /// 書き出し先
+    output: W,
+    /// サンプルのフォーマット
+    format: WavSampleFormat,
+    /// factチャンクのサンプル数の位置（浮動小数点数のみ）
+    fact_position: Option<u64>,
+    /// dataチャンクのサイズの位置
+    data_size_position: u64,
+    /// ヘッダを含めた書き込み済みのバイト数
+    total_size: u64,
+    /// 書き込んだフレーム数
+    num_frames: u64,
+    /// 書き込みバッファ
+    buffer: [u8; WRITE_BUFFER_SIZE],
+    /// 書き込みバッファのデータ長
+    buffer_length: usize,
+}
+
+impl<W: WavOutput> WavWriter<W> {
+    /// ヘッダを書き出して作成
+    /// SPCファイルヘッダを与えた場合は曲名・作曲者・ゲーム名をLIST/INFOチャンクに書き込む
+    pub fn new(
+        output: W,
+        format: WavSampleFormat,
+        header: Option<&SPCFileHeader>,
+    ) -> Result<Self, W::Error> {
+        let mut writer = Self {
+            output,
+            format,
+            fact_position: None,
+            data_size_position: 0,
+            total_size: 0,
+            num_frames: 0,
+            buffer: [0; WRITE_BUFFER_SIZE],
+            buffer_length: 0,
+        };
+
+        // RIFFヘッダ（サイズはfinalize時に確定）
+        writer.write_bytes(b"RIFF")?;
+        writer.write_bytes(&0u32.to_le_bytes())?;
+        writer.write_bytes(b"WAVE")?;
+
+        // RF64に切り替える時にds64チャンクで置き換える領域
+        writer.write_bytes(b"JUNK")?;
+        writer.write_bytes(&(DS64_CHUNK_SIZE as u32).to_le_bytes())?;
+        writer.write_bytes(&[0; DS64_CHUNK_SIZE])?;
+
+        // fmtチャンク
+        let bytes_per_sample = format.bytes_per_sample() as u16;
+        let block_align = NUM_CHANNELS * bytes_per_sample;
+        let fmt_size: u32 = match format {
+            WavSampleFormat::Float32 => 18,
+            _ => 16,
+        };
+        writer.write_bytes(b"fmt ")?;
+        writer.write_bytes(&fmt_size.to_le_bytes())?;
+        writer.write_bytes(&format.format_tag().to_le_bytes())?;
+        writer.write_bytes(&NUM_CHANNELS.to_le_bytes())?;
+        writer.write_bytes(&OUTPUT_SAMPLING_RATE.to_le_bytes())?;
+        writer.write_bytes(&(OUTPUT_SAMPLING_RATE * block_align as u32).to_le_bytes())?;
+        writer.write_bytes(&block_align.to_le_bytes())?;
+        writer.write_bytes(&(8 * bytes_per_sample).to_le_bytes())?;
+        if format == WavSampleFormat::Float32 {
+            // 拡張情報なし
+            writer.write_bytes(&0u16.to_le_bytes())?;
+            // PCM以外はfactチャンクが必要
+            writer.write_bytes(b"fact")?;
+            writer.write_bytes(&4u32.to_le_bytes())?;
+            writer.fact_position = Some(writer.total_size);
+            writer.write_bytes(&0u32.to_le_bytes())?;
+        }
+
+        // LIST/INFOチャンク
+        if let Some(header) = header {
+            writer.write_info_chunk(header)?;
+        }
+
+        // dataチャンク（サイズはfinalize時に確定）
+        writer.write_bytes(b"data")?;
+        writer.data_size_position = writer.total_size;
+        writer.write_bytes(&0u32.to_le_bytes())?;
+        writer.flush()?;
+
+        Ok(writer)
+    }
+
+    /// LIST/INFOチャンクの書き出し
+    fn write_info_chunk(&mut self, header: &SPCFileHeader) -> Result<(), W::Error> {
+        let title = header.music_title_text();
+        let artist = header.composer_text();
+        let game = header.game_title_text();
+        let entries = [
+            (b"INAM", title.as_str()),
+            (b"IART", artist.as_str()),
+            (b"IPRD", game.as_str()),
+        ];
+
+        // サブチャンクは終端のヌル文字を含め、2バイト境界に揃える
+        let entry_size = |text: &str| {
+            let length = truncate_text(text, INFO_TEXT_MAX_LENGTH).len() + 1;
+            8 + length + (length & 1)
+        };
+        let list_size: usize = 4 + entries
+            .iter()
+            .filter(|(_, text)| !text.is_empty())
+            .map(|(_, text)| entry_size(text))
+            .sum::<usize>();
+        if list_size == 4 {
+            return Ok(());
+        }
+
+        self.write_bytes(b"LIST")?;
+        self.write_bytes(&(list_size as u32).to_le_bytes())?;
+        self.write_bytes(b"INFO")?;
+        for (id, text) in entries.iter().filter(|(_, text)| !text.is_empty()) {
+            let text = truncate_text(text, INFO_TEXT_MAX_LENGTH);
+            let length = text.len() + 1;
+            self.write_bytes(*id)?;
+            self.write_bytes(&(length as u32).to_le_bytes())?;
+            self.write_bytes(text.as_bytes())?;
+            self.write_bytes(&[0; 2][..1 + (length & 1)])?;
+        }
+
+        Ok(())
+    }
+
+    /// バッファ経由でバイト列を書き出し
+    fn write_bytes(&mut self, data: &[u8]) -> Result<(), W::Error> {
+        for chunk in data.chunks(WRITE_BUFFER_SIZE) {
+            if self.buffer_length + chunk.len() > WRITE_BUFFER_SIZE {
+                self.flush()?;
+            }
+            self.buffer[self.buffer_length..self.buffer_length + chunk.len()]
+                .copy_from_slice(chunk);
+            self.buffer_length += chunk.len();
+            self.total_size += chunk.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// バッファの内容を書き出し
+    fn flush(&mut self) -> Result<(), W::Error> {
+        if self.buffer_length > 0 {
+            self.output.write(&self.buffer[..self.buffer_length])?;
+            self.buffer_length = 0;
+        }
+        Ok(())
+    }
+
+    /// フレームの書き出し
+    pub fn write_frames(&mut self, frames: &[[i16; 2]]) -> Result<(), W::Error> {
+        for frame in frames.iter() {
+            for &sample in frame.iter() {
+                match self.format {
+                    WavSampleFormat::PCM16 => self.write_bytes(&sample.to_le_bytes())?,
+                    WavSampleFormat::PCM24 => {
+                        self.write_bytes(&((sample as i32) << 8).to_le_bytes()[..3])?
+                    }
+                    WavSampleFormat::Float32 => {
+                        self.write_bytes(&(sample as f32 / 32768.0).to_le_bytes())?
+                    }
+                }
+            }
+        }
+        self.num_frames += frames.len() as u64;
+        Ok(())
+    }
+
+    /// 書き込んだフレーム数
+    pub fn num_frames(&self) -> u64 {
+        self.num_frames
+    }
+
+    /// ヘッダのサイズ情報を確定して書き出しを終える。書き出し先を返す
+    pub fn finalize(mut self) -> Result<W, W::Error> {
+        self.flush()?;
+
+        let data_size =
+            self.num_frames * NUM_CHANNELS as u64 * self.format.bytes_per_sample() as u64;
+        let riff_size = self.total_size - 8;
+
+        // ステレオのためdataチャンクは常に偶数長でパディングは不要
+        if riff_size <= u32::MAX as u64 && data_size <= u32::MAX as u64 {
+            self.output
+                .write_at(RIFF_HEADER_POSITION + 4, &(riff_size as u32).to_le_bytes())?;
+            self.output
+                .write_at(self.data_size_position, &(data_size as u32).to_le_bytes())?;
+            if let Some(position) = self.fact_position {
+                self.output
+                    .write_at(position, &(self.num_frames as u32).to_le_bytes())?;
+            }
+        } else {
+            // RF64：32bitのサイズは全て0xFFFFFFFFとしds64チャンクに64bitで記録
+            self.output.write_at(RIFF_HEADER_POSITION, b"RF64")?;
+            self.output
+                .write_at(RIFF_HEADER_POSITION + 4, &u32::MAX.to_le_bytes())?;
+            let mut ds64 = [0u8; 8 + DS64_CHUNK_SIZE];
+            ds64[0..4].copy_from_slice(b"ds64");
+            ds64[4..8].copy_from_slice(&(DS64_CHUNK_SIZE as u32).to_le_bytes());
+            ds64[8..16].copy_from_slice(&riff_size.to_le_bytes());
+            ds64[16..24].copy_from_slice(&data_size.to_le_bytes());
+            ds64[24..32].copy_from_slice(&self.num_frames.to_le_bytes());
+            self.output.write_at(DS64_CHUNK_POSITION, &ds64)?;
+            self.output
+                .write_at(self.data_size_position, &u32::MAX.to_le_bytes())?;
+            if let Some(position) = self.fact_position {
+                self.output.write_at(position, &u32::MAX.to_le_bytes())?;
+            }
+        }
+
+        Ok(self.output)
+    }
+}
+
+/// レンダラの出力をボイスごと（ボイス0-7・エコー成分の順）のWAVに書き出す。書き出したサンプル数を返す
+/// 1回の演奏で全てのステムを書き出す。演奏時間が設定されていない場合は何もしない
+pub fn write_stems<W: WavOutput>(
+    renderer: &mut Renderer,
+    writers: &mut [WavWriter<W>; NUM_STEMS],
+) -> Result<u64, W::Error> {
+    if renderer.total_samples().is_none() {
+        return Ok(0);
+    }
+
+    let mut mix = [[0i16; 2]; STEM_BLOCK_SIZE];
+    let mut stems = [SDSPStems::default(); STEM_BLOCK_SIZE];
+    let mut frames = [[0i16; 2]; STEM_BLOCK_SIZE];
+    let mut total = 0;
+    loop {
+        let num_samples = renderer.render_with_stems(&mut mix, &mut stems);
+        if num_samples == 0 {
+            break;
+        }
+        for (index, writer) in writers.iter_mut().enumerate() {
+            for (frame, stem) in frames.iter_mut().zip(stems[..num_samples].iter()) {
+                *frame = if index < 8 {
+                    stem.voice[index]
+                } else {
+                    stem.echo
+                };
+            }
+            writer.write_frames(&frames[..num_samples])?;
+        }
+        total += num_samples as u64;
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+    use std::vec::Vec;
+
+    /// メモリ上への書き出し
+    struct VecOutput(Vec<u8>);
+
+    impl WavOutput for VecOutput {
+        type Error = Infallible;
+
+        fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.0.extend_from_slice(data);
+            Ok(())
+        }
+
+        fn write_at(&mut self, position: u64, data: &[u8]) -> Result<(), Self::Error> {
+            let position = position as usize;
+            self.0[position..position + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+    }
+
+    fn read_u32(data: &[u8], position: usize) -> u32 {
+        u32::from_le_bytes(data[position..position + 4].try_into().unwrap())
+    }
+
+    fn read_u64(data: &[u8], position: usize) -> u64 {
+        u64::from_le_bytes(data[position..position + 8].try_into().unwrap())
+    }
+
+    /// チャンクを先頭から辿り、(ID, データ位置, サイズ)の一覧を得る
+    fn chunks(data: &[u8]) -> Vec<([u8; 4], usize, u32)> {
+        let mut chunks = Vec::new();
+        let mut position = 12;
+        while position < data.len() {
+            let id: [u8; 4] = data[position..position + 4].try_into().unwrap();
+            let size = read_u32(data, position + 4);
+            chunks.push((id, position + 8, size));
+            if size == u32::MAX {
+                break;
+            }
+            position += 8 + size as usize + (size as usize & 1);
+        }
+        chunks
+    }
+
+    fn test_header() -> SPCFileHeader {
+        let mut header = SPCFileHeader::new();
+        // 奇数長・偶数長の両方でパディングを確認する
+        header.set_music_title("Eyes on Me!");
+        header.set_composer("Composer");
+        header.set_game_title("Game");
+        header
+    }
+
+    fn write_wav(format: WavSampleFormat, num_frames: usize) -> WavWriter<VecOutput> {
+        let mut writer =
+            WavWriter::new(VecOutput(Vec::new()), format, Some(&test_header())).unwrap();
+        let frames: Vec<[i16; 2]> = (0..num_frames as i16).map(|i| [i, -i]).collect();
+        writer.write_frames(&frames).unwrap();
+        writer
+    }
+
+    #[test]
+    fn header_sizes() {
+        const NUM_FRAMES: usize = 1001;
+        for format in [
+            WavSampleFormat::PCM16,
+            WavSampleFormat::PCM24,
+            WavSampleFormat::Float32,
+        ] {
+            let data = write_wav(format, NUM_FRAMES).finalize().unwrap().0;
+            assert_eq!(&data[0..4], b"RIFF");
+            assert_eq!(read_u32(&data, 4) as usize, data.len() - 8);
+            assert_eq!(&data[8..12], b"WAVE");
+
+            let chunks = chunks(&data);
+            let ids: Vec<&[u8; 4]> = chunks.iter().map(|(id, _, _)| id).collect();
+            let expected_ids: Vec<&[u8; 4]> = match format {
+                WavSampleFormat::Float32 => std::vec![b"JUNK", b"fmt ", b"fact", b"LIST", b"data"],
+                _ => std::vec![b"JUNK", b"fmt ", b"LIST", b"data"],
+            };
+            assert_eq!(ids, expected_ids, "{:?}", format);
+
+            let bytes_per_sample = format.bytes_per_sample();
+            for &(id, position, size) in chunks.iter() {
+                match &id {
+                    b"JUNK" => {
+                        assert_eq!(position as u64, DS64_CHUNK_POSITION + 8);
+                        assert_eq!(size as usize, DS64_CHUNK_SIZE);
+                    }
+                    b"fmt " => {
+                        let expected = if format == WavSampleFormat::Float32 {
+                            18
+                        } else {
+                            16
+                        };
+                        assert_eq!(size, expected);
+                        assert_eq!(read_u32(&data, position + 4), OUTPUT_SAMPLING_RATE);
+                        assert_eq!(
+                            u16::from_le_bytes([data[position + 12], data[position + 13]]) as usize,
+                            2 * bytes_per_sample
+                        );
+                    }
+                    b"fact" => {
+                        assert_eq!(size, 4);
+                        assert_eq!(read_u32(&data, position) as usize, NUM_FRAMES);
+                    }
+                    b"LIST" => {
+                        assert_eq!(&data[position..position + 4], b"INFO");
+                        // 曲名は終端込みで12バイトでパディングなし、作曲者・ゲーム名は奇数長で1バイトのパディングが入る
+                        let entries = &data[position + 4..position + size as usize];
+                        assert_eq!(&entries[0..4], b"INAM");
+                        assert_eq!(read_u32(entries, 4), 12);
+                        assert_eq!(&entries[8..20], b"Eyes on Me!\0");
+                        assert_eq!(&entries[20..24], b"IART");
+                        assert_eq!(read_u32(entries, 24), 9);
+                        assert_eq!(&entries[28..37], b"Composer\0");
+                        assert_eq!(&entries[38..42], b"IPRD");
+                        assert_eq!(read_u32(entries, 42), 5);
+                        assert_eq!(&entries[46..51], b"Game\0");
+                        assert_eq!(entries.len(), 52);
+                    }
+                    b"data" => {
+                        assert_eq!(size as usize, NUM_FRAMES * 2 * bytes_per_sample);
+                        assert_eq!(position + size as usize, data.len());
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn finalize_switches_to_rf64() {
+        const NUM_FRAMES: u64 = (u32::MAX as u64) / 4 + 10;
+        for format in [WavSampleFormat::PCM16, WavSampleFormat::Float32] {
+            let mut writer = write_wav(format, 10);
+            // 4GiBを超えるデータを書き込んだことにする
+            writer.num_frames = NUM_FRAMES;
+            let data = writer.finalize().unwrap().0;
+
+            assert_eq!(&data[0..4], b"RF64");
+            assert_eq!(read_u32(&data, 4), u32::MAX);
+            let ds64 = DS64_CHUNK_POSITION as usize;
+            assert_eq!(&data[ds64..ds64 + 4], b"ds64");
+            assert_eq!(read_u32(&data, ds64 + 4) as usize, DS64_CHUNK_SIZE);
+            assert_eq!(read_u64(&data, ds64 + 8) as usize, data.len() - 8);
+            assert_eq!(
+                read_u64(&data, ds64 + 16),
+                NUM_FRAMES * 2 * format.bytes_per_sample() as u64
+            );
+            assert_eq!(read_u64(&data, ds64 + 24), NUM_FRAMES);
+
+            let chunks = chunks(&data);
+            let (_, _, data_size) = chunks.iter().find(|(id, _, _)| id == b"data").unwrap();
+            assert_eq!(*data_size, u32::MAX);
+            if let Some((_, position, _)) = chunks.iter().find(|(id, _, _)| id == b"fact") {
+                assert_eq!(read_u32(&data, *position), u32::MAX);
+            }
+        }
+    }
+
+    #[test]
+    fn truncate_text_on_char_boundary() {
+        // 半角カナはUTF-8で3バイト
+        let text = "ｱｲｳ";
+        assert_eq!(truncate_text(text, 9), "ｱｲｳ");
+        assert_eq!(truncate_text(text, 8), "ｱｲ");
+        assert_eq!(truncate_text(text, 2), "");
+        assert_eq!(truncate_text("abc", 256), "abc");
+    }
+}